@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::{relative_target, Cell, Instruction, Program, StdFunc, MAX_CALL_DEPTH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackAnalysis {
+    pub max_depth: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnalysisError {
+    StackUnderflow { index: usize },
+    InconsistentDepth { index: usize, expected: usize, found: usize },
+    InvalidJump { index: usize, target: usize },
+    UnknownString { index: usize },
+    CallDepthExceeded { index: usize },
+    InvalidRelativeJump { index: usize, offset: i16 },
+}
+
+impl Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::StackUnderflow { index } => write!(f, "instruction {} underflows the stack", index),
+            AnalysisError::InconsistentDepth { index, expected, found } => write!(f, "instruction {} is reached with stack depths {} and {}", index, expected, found),
+            AnalysisError::InvalidJump { index, target } => write!(f, "instruction {} jumps to invalid target {}", index, target),
+            AnalysisError::UnknownString { index } => write!(f, "instruction {} prints a string that was not pushed by pushstr or pushcountedstr", index),
+            AnalysisError::CallDepthExceeded { index } => write!(f, "instruction {} exceeds the maximum call depth", index),
+            AnalysisError::InvalidRelativeJump { index, offset } => write!(f, "instruction {} jumps by {} out of the program", index, offset),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+// One path through the program: values are unknown, only the depth is tracked along with where the
+// strings pushed by PushStr and PushCountedStr start so printing them knows how much it pops
+#[derive(Clone)]
+struct State {
+    index: usize,
+    depth: usize,
+    strings: Vec<usize>,
+    returns: Vec<usize>,
+}
+
+enum Flow {
+    Next,
+    Jump(usize),
+    Branch(usize),
+    Call(usize),
+    Return,
+    Stop,
+}
+
+// Values popped, values pushed and where execution continues, relative jumps are resolved from `index`
+fn effect<T>(index: usize, instruction: &Instruction<T>) -> (usize, usize, Flow) {
+    let relative = |offset: &i16| index.wrapping_add_signed(*offset as isize);
+    match instruction {
+        Instruction::Push(_)
+        | Instruction::PushChar(_)
+        | Instruction::Depth
+        | Instruction::Load(_)
+        | Instruction::FromR
+        | Instruction::LoadArg(_) => (0, 1, Flow::Next),
+        Instruction::PushStr(value) => (0, value.len() + 1, Flow::Next),
+        Instruction::PushAll(values) => (0, values.len(), Flow::Next),
+        Instruction::PushI16(_) => (0, 2, Flow::Next),
+        Instruction::Pop
+        | Instruction::Store(_)
+        | Instruction::ToR
+        | Instruction::Assert(_)
+        | Instruction::StoreArg(_) => (1, 0, Flow::Next),
+        Instruction::DropN(count) => (*count as usize, 0, Flow::Next),
+        Instruction::Dup => (1, 2, Flow::Next),
+        Instruction::Swap => (2, 2, Flow::Next),
+        Instruction::Over => (2, 3, Flow::Next),
+        Instruction::Pick(depth) => (*depth as usize + 1, *depth as usize + 2, Flow::Next),
+        Instruction::Rot(count) => (*count as usize, *count as usize, Flow::Next),
+        // Depends on the current depth, handled by the caller
+        Instruction::ClearStack => (0, 0, Flow::Next),
+        Instruction::Nop => (0, 0, Flow::Next),
+        Instruction::MemStore => (2, 0, Flow::Next),
+        Instruction::MemLoad
+        | Instruction::Neg
+        | Instruction::Inc
+        | Instruction::Dec
+        | Instruction::ToBool
+        | Instruction::ToUpper
+        | Instruction::ToLower
+        | Instruction::Not => (1, 1, Flow::Next),
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::AddCarry
+        | Instruction::SubBorrow
+        | Instruction::AddI
+        | Instruction::SubI
+        | Instruction::MulI
+        | Instruction::DivI
+        | Instruction::ModI
+        | Instruction::Lt
+        | Instruction::Gt
+        | Instruction::Eq
+        | Instruction::Cmp
+        | Instruction::Min
+        | Instruction::Max
+        | Instruction::And
+        | Instruction::Or
+        | Instruction::Xor
+        | Instruction::Shl
+        | Instruction::Shr => (2, 1, Flow::Next),
+        Instruction::AddI16
+        | Instruction::SubI16 => (4, 2, Flow::Next),
+        Instruction::JmpEq(location)
+        | Instruction::JmpNeq(location)
+        | Instruction::JmpLt(location)
+        | Instruction::JmpGt(location)
+        | Instruction::JmpLe(location)
+        | Instruction::JmpGe(location) => (2, 2, Flow::Branch(*location)),
+        Instruction::Jmp(location) => (0, 0, Flow::Jump(*location)),
+        Instruction::JmpOverflow(location) => (0, 0, Flow::Branch(*location)),
+        Instruction::JmpIf(location)
+        | Instruction::JmpIfNot(location) => (1, 0, Flow::Branch(*location)),
+        Instruction::JmpRel(offset) => (0, 0, Flow::Jump(relative(offset))),
+        Instruction::JmpEqRel(offset)
+        | Instruction::JmpNeqRel(offset) => (2, 2, Flow::Branch(relative(offset))),
+        Instruction::JmpIfRel(offset)
+        | Instruction::JmpIfNotRel(offset) => (1, 0, Flow::Branch(relative(offset))),
+        Instruction::Call(location) => (0, 0, Flow::Call(*location)),
+        Instruction::Ret => (0, 0, Flow::Return),
+        Instruction::StdCall(id) => match StdFunc::from_id(*id) {
+            Some(StdFunc::PrintU8 | StdFunc::PrintChar | StdFunc::PrintHex | StdFunc::PrintMemString | StdFunc::PrintU8Ln) => (1, 0, Flow::Next),
+            Some(StdFunc::Clone) => (1, 2, Flow::Next),
+            Some(StdFunc::ReadU8 | StdFunc::ReadChar) => (0, 1, Flow::Next),
+            Some(StdFunc::Checksum | StdFunc::Cycles | StdFunc::Rand) => (0, 1, Flow::Next),
+            Some(StdFunc::PrintStack) => (0, 0, Flow::Next),
+            // Depends on the string on the stack, handled by the caller
+            Some(StdFunc::PrintString | StdFunc::PrintCountedString) => (0, 0, Flow::Next),
+            // An unknown function fails at runtime, nothing runs after it
+            None => (0, 0, Flow::Stop),
+        },
+        Instruction::PushCountedStr(value) => (0, value.len() + 1, Flow::Next),
+        Instruction::DupN(count) => (*count as usize, *count as usize * 2, Flow::Next),
+        Instruction::SetLocal(_, _) => (0, 0, Flow::Next),
+        Instruction::Halt(_)
+        | Instruction::Interupt => (0, 0, Flow::Stop),
+    }
+}
+
+impl<T: Cell> Program<T> {
+
+    // Follows every branch without looking at values, so each instruction must always be reached
+    // with the same stack depth (loops have to leave the stack as they found it)
+    pub fn analyze_stack(&self) -> Result<StackAnalysis, AnalysisError> {
+        let mut max_depth = 0;
+        let mut seen: HashMap<(usize, Vec<usize>), (usize, Vec<usize>)> = HashMap::new();
+        let mut pending = vec![State { index: 0, depth: 0, strings: Vec::new(), returns: Vec::new() }];
+        while let Some(mut state) = pending.pop() {
+            let index = state.index;
+            // Running past the last instruction halts
+            if index >= self.len() {
+                continue;
+            }
+            match seen.get_mut(&(index, state.returns.clone())) {
+                Some((depth, _)) if *depth != state.depth => {
+                    return Err(AnalysisError::InconsistentDepth { index, expected: *depth, found: state.depth });
+                },
+                Some((_, strings)) => {
+                    // Only keep the strings every path agrees on, and go on only if that changed something
+                    let count = strings.len();
+                    strings.retain(|position| state.strings.contains(position));
+                    if strings.len() == count {
+                        continue;
+                    }
+                    state.strings = strings.clone();
+                },
+                None => _ = seen.insert((index, state.returns.clone()), (state.depth, state.strings.clone())),
+            }
+
+            let instruction = &self.instructions[index];
+            if let Some(offset) = instruction.relative_offset() {
+                if relative_target(index, offset).is_none_or(|target| target > self.len()) {
+                    return Err(AnalysisError::InvalidRelativeJump { index, offset });
+                }
+            }
+            let (pops, pushes, flow) = match instruction {
+                Instruction::ClearStack => (state.depth, 0, Flow::Next),
+                Instruction::StdCall(id) if matches!(StdFunc::from_id(*id), Some(StdFunc::PrintString | StdFunc::PrintCountedString)) => {
+                    let start = *state.strings.last().ok_or(AnalysisError::UnknownString { index })?;
+                    (state.depth - start, 0, Flow::Next)
+                },
+                _ => effect(index, instruction),
+            };
+            if pops > state.depth {
+                return Err(AnalysisError::StackUnderflow { index });
+            }
+            state.depth -= pops;
+            state.strings.retain(|position| *position < state.depth);
+            if let Instruction::PushStr(_) | Instruction::PushCountedStr(_) = instruction {
+                state.strings.push(state.depth);
+            }
+            state.depth += pushes;
+            max_depth = max_depth.max(state.depth);
+
+            let target = match flow {
+                Flow::Jump(target) | Flow::Branch(target) | Flow::Call(target) => target,
+                _ => 0,
+            };
+            if target > self.len() {
+                return Err(AnalysisError::InvalidJump { index, target });
+            }
+            match flow {
+                Flow::Next => pending.push(State { index: index + 1, ..state }),
+                Flow::Jump(target) => pending.push(State { index: target, ..state }),
+                Flow::Branch(target) => {
+                    pending.push(State { index: target, ..state.clone() });
+                    pending.push(State { index: index + 1, ..state });
+                },
+                Flow::Call(target) => {
+                    if state.returns.len() >= MAX_CALL_DEPTH {
+                        return Err(AnalysisError::CallDepthExceeded { index });
+                    }
+                    state.returns.push(index + 1);
+                    pending.push(State { index: target, ..state });
+                },
+                // Returning with an empty call stack fails at runtime, nothing runs after it
+                Flow::Return => {
+                    if let Some(address) = state.returns.pop() {
+                        pending.push(State { index: address, ..state });
+                    }
+                },
+                Flow::Stop => {},
+            }
+        }
+        Ok(StackAnalysis { max_depth })
+    }
+
+    // Marks every instruction some path from index 0 can execute, whatever the values on the stack.
+    // A call is assumed to return, so the instruction after it is reachable
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.len()];
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            if index >= self.len() || reached[index] {
+                continue;
+            }
+            reached[index] = true;
+            match effect(index, &self.instructions[index]).2 {
+                Flow::Next => pending.push(index + 1),
+                Flow::Jump(target) => pending.push(target),
+                Flow::Branch(target) | Flow::Call(target) => pending.extend([target, index + 1]),
+                Flow::Return | Flow::Stop => {},
+            }
+        }
+        reached
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn hello_world_depth_counts_the_terminator() {
+        let mut program = Program::new();
+        program.push_str("Hello, World!\n").std_call(StdFunc::PrintString);
+        assert_eq!(program.analyze_stack(), Ok(StackAnalysis { max_depth: 15 }));
+    }
+
+    #[test]
+    fn branches_and_calls_are_followed() {
+        let mut program = Program::parse("
+            push 3
+        loop:
+            dup
+            jmpifnot end
+            dec
+            push 9
+            push 9
+            pop
+            pop
+            jmp loop
+        end:
+            pushstr \"ab\"
+            call sub
+            stdcall 2
+            halt 0
+        sub:
+            push 1
+            push 2
+            add
+            pop
+            ret
+        ").unwrap();
+        assert_eq!(program.analyze_stack(), Ok(StackAnalysis { max_depth: 6 }));
+        program.set(9, Instruction::Nop).unwrap();
+        assert_eq!(program.analyze_stack(), Err(AnalysisError::UnknownString { index: 11 }));
+    }
+
+    #[test]
+    fn loops_must_keep_the_depth() {
+        let program = Program::parse("l:\npush 1\njmp l").unwrap();
+        assert_eq!(program.analyze_stack(), Err(AnalysisError::InconsistentDepth { index: 0, expected: 0, found: 1 }));
+    }
+
+    #[test]
+    fn underflow_is_reported() {
+        assert_eq!(Program::parse("add").unwrap().analyze_stack(), Err(AnalysisError::StackUnderflow { index: 0 }));
+    }
+
+    #[test]
+    fn code_after_a_jump_is_unreachable() {
+        let mut program = Program::new();
+        program.push_u8(1).jmp(3).push_u8(9).call(6).halt(0).nop().ret();
+        assert_eq!(program.reachable(), vec![true, true, false, true, true, false, true]);
+    }
+
+    #[test]
+    fn relative_jumps_and_interupts_are_followed() {
+        let mut program = Program::new();
+        program.jmp_rel(2).nop().push_u8(0).jmp_if(0).interupt().nop();
+        assert_eq!(program.reachable(), vec![true, false, true, true, true, false]);
+        assert!(Program::new().reachable().is_empty());
+    }
+
+    #[test]
+    fn relative_jumps_out_of_range_are_rejected() {
+        let program = Program::from(vec![Instruction::Nop, Instruction::JmpRel(-2)]);
+        assert_eq!(program.analyze_stack(), Err(AnalysisError::InvalidRelativeJump { index: 1, offset: -2 }));
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::{Cell, Instruction, Program};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    UndefinedLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+    MissingOperand { line: usize },
+    UnexpectedOperand { line: usize },
+    InvalidOperand { line: usize, operand: String },
+    UnterminatedString { line: usize },
+    OutOfRange { line: usize, operand: String },
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic '{}'", line, mnemonic),
+            AsmError::UndefinedLabel { line, label } => write!(f, "line {}: undefined label '{}'", line, label),
+            AsmError::DuplicateLabel { line, label } => write!(f, "line {}: duplicate label '{}'", line, label),
+            AsmError::MissingOperand { line } => write!(f, "line {}: missing operand", line),
+            AsmError::UnexpectedOperand { line } => write!(f, "line {}: unexpected operand", line),
+            AsmError::InvalidOperand { line, operand } => write!(f, "line {}: invalid operand '{}'", line, operand),
+            AsmError::UnterminatedString { line } => write!(f, "line {}: unterminated string", line),
+            AsmError::OutOfRange { line, operand } => write!(f, "line {}: '{}' does not fit in a byte", line, operand),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+enum Line<'a> {
+    Label(&'a str),
+    Instruction(&'a str, Option<&'a str>),
+}
+
+fn split_line(source: &str) -> Option<Line<'_>> {
+    let source = source.trim();
+    // Whole-line comments start with ';' or '#'
+    if source.is_empty() || source.starts_with(';') || source.starts_with('#') {
+        return None;
+    }
+    // Only a single identifier makes a label, so an operand that happens to end with ':' stays an operand
+    if let Some(label) = source.strip_suffix(':').map(str::trim).filter(|label| is_label(label)) {
+        return Some(Line::Label(label));
+    }
+    match source.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => Some(Line::Instruction(mnemonic, Some(operand.trim()))),
+        None => Some(Line::Instruction(source, None)),
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+struct Assembler<'a> {
+    labels: HashMap<&'a str, usize>,
+    line: usize,
+}
+
+impl<'a> Assembler<'a> {
+
+    fn no_operand(&self, operand: Option<&str>, instruction: Instruction) -> Result<Instruction, AsmError> {
+        match operand {
+            Some(_) => Err(AsmError::UnexpectedOperand { line: self.line }),
+            None => Ok(instruction),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self, operand: Option<&str>) -> Result<T, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        operand.parse().map_err(|_| AsmError::InvalidOperand { line: self.line, operand: operand.to_string() })
+    }
+
+    // Negative values down to -128 are stored as their two's complement, so `-1` is 255
+    fn byte(&self, operand: Option<&str>) -> Result<u8, AsmError> {
+        let value: i64 = self.number(operand)?;
+        match value {
+            -128..=-1 => Ok(value as i8 as u8),
+            0..=255 => Ok(value as u8),
+            _ => Err(AsmError::OutOfRange { line: self.line, operand: operand.unwrap_or_default().to_string() }),
+        }
+    }
+
+    // Byte lists are separated by commas and/or whitespace
+    fn bytes(&self, operand: Option<&str>) -> Result<Vec<u8>, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        operand.split(|chr: char| chr == ',' || chr.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| self.byte(Some(value)))
+            .collect()
+    }
+
+    // `slot, value`, the value may be negative like a push operand
+    fn set_local(&self, operand: Option<&str>) -> Result<Instruction, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        let (slot, value) = operand.split_once(',').ok_or_else(|| AsmError::InvalidOperand { line: self.line, operand: operand.to_string() })?;
+        Ok(Instruction::SetLocal(self.number(Some(slot.trim()))?, self.byte(Some(value.trim()))?))
+    }
+
+    // Quoted literal, the escapes \n, \t, \r, \0, \\, \", \' and \u{..} are supported
+    fn string(&self, operand: Option<&str>) -> Result<String, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        let invalid = || AsmError::InvalidOperand { line: self.line, operand: operand.to_string() };
+        let mut chars = operand.strip_prefix('"').ok_or_else(invalid)?.chars();
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => value.push(self.escape(operand, &mut chars)?),
+                Some(chr) => value.push(chr),
+                None => return Err(AsmError::UnterminatedString { line: self.line }),
+            }
+        }
+        if !chars.as_str().is_empty() {
+            return Err(invalid());
+        }
+        Ok(value)
+    }
+
+    // Either a quoted literal with the same escapes as strings or a bare character
+    fn char(&self, operand: Option<&str>) -> Result<char, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        let invalid = || AsmError::InvalidOperand { line: self.line, operand: operand.to_string() };
+        let Some(quoted) = operand.strip_prefix('\'').filter(|quoted| !quoted.is_empty()) else {
+            return self.number(Some(operand));
+        };
+        let mut chars = quoted.chars();
+        let value = match chars.next() {
+            Some('\\') => self.escape(operand, &mut chars)?,
+            Some(chr) => chr,
+            None => return Err(invalid()),
+        };
+        if chars.as_str() != "'" {
+            return Err(invalid());
+        }
+        Ok(value)
+    }
+
+    // Reads what follows a backslash in a quoted literal
+    fn escape(&self, operand: &str, chars: &mut std::str::Chars) -> Result<char, AsmError> {
+        let invalid = || AsmError::InvalidOperand { line: self.line, operand: operand.to_string() };
+        match chars.next() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('\'') => Ok('\''),
+            Some('u') => {
+                let rest = chars.as_str().strip_prefix('{').ok_or_else(invalid)?;
+                let (hex, rest) = rest.split_once('}').ok_or_else(invalid)?;
+                let value = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).ok_or_else(invalid)?;
+                *chars = rest.chars();
+                Ok(value)
+            },
+            Some(_) => Err(invalid()),
+            None => Err(AsmError::UnterminatedString { line: self.line }),
+        }
+    }
+
+    // Jump targets are either a label name or a raw instruction index
+    fn target(&self, operand: Option<&str>) -> Result<usize, AsmError> {
+        let operand = operand.ok_or(AsmError::MissingOperand { line: self.line })?;
+        if let Ok(location) = operand.parse() {
+            return Ok(location);
+        }
+        self.labels.get(operand).copied().ok_or(AsmError::UndefinedLabel { line: self.line, label: operand.to_string() })
+    }
+
+    fn instruction(&self, mnemonic: &str, operand: Option<&str>) -> Result<Instruction, AsmError> {
+        match mnemonic.to_lowercase().as_str() {
+            "push" => Ok(Instruction::Push(self.byte(operand)?)),
+            ".string" | "pushstr" => Ok(Instruction::PushStr(self.string(operand)?)),
+            "pushcountedstr" => Ok(Instruction::PushCountedStr(self.string(operand)?)),
+            "pushchar" => Ok(Instruction::PushChar(self.char(operand)?)),
+            "pushall" => Ok(Instruction::PushAll(self.bytes(operand)?)),
+            "pop" => self.no_operand(operand, Instruction::Pop),
+            "dropn" => Ok(Instruction::DropN(self.number(operand)?)),
+            "dup" => self.no_operand(operand, Instruction::Dup),
+            "dupn" => Ok(Instruction::DupN(self.number(operand)?)),
+            "swap" => self.no_operand(operand, Instruction::Swap),
+            "over" => self.no_operand(operand, Instruction::Over),
+            "pick" => Ok(Instruction::Pick(self.number(operand)?)),
+            "rot" => Ok(Instruction::Rot(self.number(operand)?)),
+            "depth" => self.no_operand(operand, Instruction::Depth),
+            "clearstack" => self.no_operand(operand, Instruction::ClearStack),
+            "nop" => self.no_operand(operand, Instruction::Nop),
+            "store" => Ok(Instruction::Store(self.number(operand)?)),
+            "load" => Ok(Instruction::Load(self.number(operand)?)),
+            "setlocal" => self.set_local(operand),
+            "storearg" => Ok(Instruction::StoreArg(self.number(operand)?)),
+            "loadarg" => Ok(Instruction::LoadArg(self.number(operand)?)),
+            "memstore" => self.no_operand(operand, Instruction::MemStore),
+            "memload" => self.no_operand(operand, Instruction::MemLoad),
+            "add" => self.no_operand(operand, Instruction::Add),
+            "sub" => self.no_operand(operand, Instruction::Sub),
+            "mul" => self.no_operand(operand, Instruction::Mul),
+            "div" => self.no_operand(operand, Instruction::Div),
+            "mod" => self.no_operand(operand, Instruction::Mod),
+            "neg" => self.no_operand(operand, Instruction::Neg),
+            "inc" => self.no_operand(operand, Instruction::Inc),
+            "dec" => self.no_operand(operand, Instruction::Dec),
+            "addcarry" => self.no_operand(operand, Instruction::AddCarry),
+            "subborrow" => self.no_operand(operand, Instruction::SubBorrow),
+            "addi" => self.no_operand(operand, Instruction::AddI),
+            "subi" => self.no_operand(operand, Instruction::SubI),
+            "muli" => self.no_operand(operand, Instruction::MulI),
+            "divi" => self.no_operand(operand, Instruction::DivI),
+            "modi" => self.no_operand(operand, Instruction::ModI),
+            "pushi16" => Ok(Instruction::PushI16(self.number(operand)?)),
+            "addi16" => self.no_operand(operand, Instruction::AddI16),
+            "subi16" => self.no_operand(operand, Instruction::SubI16),
+            "lt" => self.no_operand(operand, Instruction::Lt),
+            "gt" => self.no_operand(operand, Instruction::Gt),
+            "eq" => self.no_operand(operand, Instruction::Eq),
+            "cmp" => self.no_operand(operand, Instruction::Cmp),
+            "min" => self.no_operand(operand, Instruction::Min),
+            "max" => self.no_operand(operand, Instruction::Max),
+            "tobool" => self.no_operand(operand, Instruction::ToBool),
+            "toupper" => self.no_operand(operand, Instruction::ToUpper),
+            "tolower" => self.no_operand(operand, Instruction::ToLower),
+            "and" => self.no_operand(operand, Instruction::And),
+            "or" => self.no_operand(operand, Instruction::Or),
+            "xor" => self.no_operand(operand, Instruction::Xor),
+            "not" => self.no_operand(operand, Instruction::Not),
+            "shl" => self.no_operand(operand, Instruction::Shl),
+            "shr" => self.no_operand(operand, Instruction::Shr),
+            "jmpeq" => Ok(Instruction::JmpEq(self.target(operand)?)),
+            "jmpneq" => Ok(Instruction::JmpNeq(self.target(operand)?)),
+            "jmplt" => Ok(Instruction::JmpLt(self.target(operand)?)),
+            "jmpgt" => Ok(Instruction::JmpGt(self.target(operand)?)),
+            "jmple" => Ok(Instruction::JmpLe(self.target(operand)?)),
+            "jmpge" => Ok(Instruction::JmpGe(self.target(operand)?)),
+            "jmp" => Ok(Instruction::Jmp(self.target(operand)?)),
+            "jmpoverflow" => Ok(Instruction::JmpOverflow(self.target(operand)?)),
+            "jmpif" => Ok(Instruction::JmpIf(self.target(operand)?)),
+            "jmpifnot" => Ok(Instruction::JmpIfNot(self.target(operand)?)),
+            "jmprel" => Ok(Instruction::JmpRel(self.number(operand)?)),
+            "jmpeqrel" => Ok(Instruction::JmpEqRel(self.number(operand)?)),
+            "jmpneqrel" => Ok(Instruction::JmpNeqRel(self.number(operand)?)),
+            "jmpifrel" => Ok(Instruction::JmpIfRel(self.number(operand)?)),
+            "jmpifnotrel" => Ok(Instruction::JmpIfNotRel(self.number(operand)?)),
+            "call" => Ok(Instruction::Call(self.target(operand)?)),
+            "ret" => self.no_operand(operand, Instruction::Ret),
+            "tor" => self.no_operand(operand, Instruction::ToR),
+            "fromr" => self.no_operand(operand, Instruction::FromR),
+            "stdcall" => Ok(Instruction::StdCall(self.number(operand)?)),
+            "assert" => Ok(Instruction::Assert(self.number(operand)?)),
+            "halt" => Ok(Instruction::Halt(self.number(operand)?)),
+            "interupt" => self.no_operand(operand, Instruction::Interupt),
+            _ => Err(AsmError::UnknownMnemonic { line: self.line, mnemonic: mnemonic.to_string() }),
+        }
+    }
+
+}
+
+// Only uses the escapes `Assembler::escape` reads back
+fn write_quoted(f: &mut std::fmt::Formatter<'_>, value: &str, quote: char) -> std::fmt::Result {
+    write!(f, "{}", quote)?;
+    for chr in value.chars() {
+        match chr {
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            '\0' => write!(f, "\\0")?,
+            '\\' => write!(f, "\\\\")?,
+            chr if chr == quote => write!(f, "\\{}", chr)?,
+            chr if chr.is_control() => write!(f, "\\u{{{:x}}}", chr as u32)?,
+            chr => write!(f, "{}", chr)?,
+        }
+    }
+    write!(f, "{}", quote)
+}
+
+// Prints the instruction the way the assembler reads it back
+impl<T: Cell> Display for Instruction<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = self.mnemonic();
+        match self {
+            Instruction::Push(value) => write!(f, "{} {}", mnemonic, value),
+            Instruction::Store(value)
+            | Instruction::Load(value)
+            | Instruction::DupN(value)
+            | Instruction::Assert(value)
+            | Instruction::StoreArg(value)
+            | Instruction::LoadArg(value)
+            | Instruction::DropN(value)
+            | Instruction::Pick(value)
+            | Instruction::Rot(value)
+            | Instruction::Halt(value) => write!(f, "{} {}", mnemonic, value),
+            Instruction::PushStr(value)
+            | Instruction::PushCountedStr(value) => {
+                write!(f, "{} ", mnemonic)?;
+                write_quoted(f, value, '"')
+            },
+            Instruction::PushI16(value) => write!(f, "{} {}", mnemonic, value),
+            Instruction::JmpRel(offset)
+            | Instruction::JmpEqRel(offset)
+            | Instruction::JmpNeqRel(offset)
+            | Instruction::JmpIfRel(offset)
+            | Instruction::JmpIfNotRel(offset) => write!(f, "{} {}", mnemonic, offset),
+            Instruction::JmpEq(location)
+            | Instruction::JmpNeq(location)
+            | Instruction::JmpLt(location)
+            | Instruction::JmpGt(location)
+            | Instruction::JmpLe(location)
+            | Instruction::JmpGe(location)
+            | Instruction::Jmp(location)
+            | Instruction::JmpOverflow(location)
+            | Instruction::JmpIf(location)
+            | Instruction::JmpIfNot(location)
+            | Instruction::Call(location)
+            | Instruction::StdCall(location) => write!(f, "{} {}", mnemonic, location),
+            Instruction::PushAll(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{} {}", mnemonic, values.join(", "))
+            },
+            Instruction::PushChar(value) => {
+                write!(f, "{} ", mnemonic)?;
+                write_quoted(f, value.encode_utf8(&mut [0; 4]), '\'')
+            },
+            Instruction::SetLocal(slot, value) => write!(f, "{} {}, {}", mnemonic, slot, value),
+            _ => write!(f, "{}", mnemonic),
+        }
+    }
+}
+
+impl Program {
+
+    pub fn parse(source: &str) -> Result<Program, AsmError> {
+        // First pass: collect label addresses
+        let mut labels = HashMap::new();
+        let mut address = 0;
+        for (index, line) in source.lines().enumerate() {
+            match split_line(line) {
+                Some(Line::Label(label)) if labels.contains_key(label) => {
+                    return Err(AsmError::DuplicateLabel { line: index + 1, label: label.to_string() });
+                },
+                Some(Line::Label(label)) => _ = labels.insert(label, address),
+                Some(Line::Instruction(_, _)) => address += 1,
+                None => {},
+            }
+        }
+
+        // Second pass: emit the instructions with resolved labels
+        let mut assembler = Assembler { labels, line: 0 };
+        let mut instructions = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            assembler.line = index + 1;
+            if let Some(Line::Instruction(mnemonic, operand)) = split_line(line) {
+                instructions.push(assembler.instruction(mnemonic, operand)?);
+            }
+        }
+        Ok(Program::from(instructions))
+    }
+
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            output.push_str(&format!("{}: {}\n", index, instruction));
+        }
+        output
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const LOOP: &str = "
+        push 10
+        push 0
+        loop:
+        jmpeq end
+        push 1
+        add
+        jmp loop
+        end:
+    ";
+
+    #[test]
+    fn parse_resolves_labels() {
+        let program = Program::parse(LOOP).unwrap();
+        assert_eq!(program.as_slice(), &[
+            Instruction::Push(10),
+            Instruction::Push(0),
+            Instruction::JmpEq(6),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::Jmp(2),
+        ]);
+        let mut vm = crate::VM::new();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[10, 10]);
+    }
+
+    #[test]
+    fn parse_reports_unknown_mnemonics() {
+        assert_eq!(Program::parse("push 1\nfrobnicate"), Err(AsmError::UnknownMnemonic { line: 2, mnemonic: "frobnicate".to_string() }));
+    }
+
+    #[test]
+    fn parse_reports_undefined_labels() {
+        assert_eq!(Program::parse("jmp nowhere"), Err(AsmError::UndefinedLabel { line: 1, label: "nowhere".to_string() }));
+    }
+
+    #[test]
+    fn disassemble_prints_indexed_mnemonics() {
+        let program = Program::parse(LOOP).unwrap();
+        assert_eq!(program.disassemble(), "0: push 10\n1: push 0\n2: jmpeq 6\n3: push 1\n4: add\n5: jmp 2\n");
+    }
+
+    #[test]
+    fn disassembly_parses_back() {
+        let program = Program::parse(LOOP).unwrap();
+        let source: String = program.iter().map(|instruction| format!("{}\n", instruction)).collect();
+        assert_eq!(Program::parse(&source), Ok(program));
+    }
+
+    #[test]
+    fn comments_and_string_directives() {
+        let program = Program::parse("
+            ; greet the world
+            # twice
+            .string \"Hello, World; #1\\n\"
+            stdcall 2
+            pushstr \"a \\\"b\\\"\"
+        ").unwrap();
+        assert_eq!(program.as_slice(), &[
+            Instruction::PushStr("Hello, World; #1\n".to_string()),
+            Instruction::StdCall(2),
+            Instruction::PushStr("a \"b\"".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn unterminated_strings_are_rejected() {
+        assert_eq!(Program::parse("nop\n.string \"abc"), Err(AsmError::UnterminatedString { line: 2 }));
+        assert_eq!(Program::parse(".string \"oops:"), Err(AsmError::UnterminatedString { line: 1 }));
+        assert!(matches!(Program::parse(".string abc"), Err(AsmError::InvalidOperand { line: 1, .. })));
+        assert!(matches!(Program::parse(".string \"a\" b"), Err(AsmError::InvalidOperand { line: 1, .. })));
+    }
+
+    #[test]
+    fn labels_are_single_identifiers() {
+        assert!(Program::parse("a.b_1:\njmp a.b_1").is_ok());
+        assert!(Program::parse("push 1 oops:").is_err());
+    }
+
+    #[test]
+    fn display_matches_the_assembler() {
+        assert_eq!(Instruction::Push(10u8).to_string(), "push 10");
+        assert_eq!(Instruction::<u8>::Jmp(5).to_string(), "jmp 5");
+        assert_eq!(Instruction::<u8>::StdCall(2).to_string(), "stdcall 2");
+        assert_eq!(Instruction::<u8>::Add.to_string(), "add");
+        assert_eq!(Instruction::<u8>::PushStr("a\n".to_string()).to_string(), "pushstr \"a\\n\"");
+        assert_eq!(Instruction::<u8>::PushChar('Z').to_string(), "pushchar 'Z'");
+        assert_eq!(Instruction::Push(300u16).to_string(), "push 300");
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for instruction in [
+            Instruction::Push(10),
+            Instruction::Jmp(0),
+            Instruction::PushAll(vec![1, 2]),
+            Instruction::Halt(3),
+            Instruction::PushChar('\n'),
+            Instruction::PushChar(' '),
+            Instruction::PushChar('\''),
+            Instruction::PushStr("\x01a \"b\"\\".to_string()),
+            Instruction::PushCountedStr("a\0b".to_string()),
+        ] {
+            assert_eq!(Program::parse(&instruction.to_string()).unwrap().as_slice(), &[instruction]);
+        }
+    }
+
+    #[test]
+    fn negative_literals_wrap_to_bytes() {
+        assert_eq!(Program::parse("push -1").unwrap().as_slice(), &[Instruction::Push(255)]);
+        assert_eq!(Program::parse("push -128\npush 255\npushall -2, 3").unwrap().as_slice(), &[
+            Instruction::Push(128),
+            Instruction::Push(255),
+            Instruction::PushAll(vec![254, 3]),
+        ]);
+    }
+
+    #[test]
+    fn literals_outside_a_byte_are_rejected() {
+        assert_eq!(Program::parse("push -129"), Err(AsmError::OutOfRange { line: 1, operand: "-129".to_string() }));
+        assert_eq!(Program::parse("\npush 256"), Err(AsmError::OutOfRange { line: 2, operand: "256".to_string() }));
+        assert_eq!(Program::parse("push x"), Err(AsmError::InvalidOperand { line: 1, operand: "x".to_string() }));
+        assert_eq!(Program::parse("push"), Err(AsmError::MissingOperand { line: 1 }));
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{Instruction, Program, ProgramFull, StdFunc, ValidationError};
+
+// Chainable helpers emitting one instruction each
+impl Program {
+
+    // Chained calls cannot return the error, a full program drops the instruction and `finalize` reports it
+    fn emit(&mut self, instruction: Instruction) -> &mut Self {
+        if let Err(error) = self.push(instruction) {
+            self.full = Some(error);
+        }
+        self
+    }
+
+    pub fn push_u8(&mut self, value: u8) -> &mut Self {
+        self.emit(Instruction::Push(value))
+    }
+
+    pub fn push_str(&mut self, value: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::PushStr(value.into()))
+    }
+
+    pub fn push_counted_str(&mut self, value: impl Into<String>) -> &mut Self {
+        self.emit(Instruction::PushCountedStr(value.into()))
+    }
+
+    pub fn push_char(&mut self, value: char) -> &mut Self {
+        self.emit(Instruction::PushChar(value))
+    }
+
+    pub fn push_all(&mut self, values: impl Into<Vec<u8>>) -> &mut Self {
+        self.emit(Instruction::PushAll(values.into()))
+    }
+
+    pub fn pop(&mut self) -> &mut Self {
+        self.emit(Instruction::Pop)
+    }
+
+    pub fn drop_n(&mut self, count: u8) -> &mut Self {
+        self.emit(Instruction::DropN(count))
+    }
+
+    pub fn dup(&mut self) -> &mut Self {
+        self.emit(Instruction::Dup)
+    }
+
+    pub fn dup_n(&mut self, count: u8) -> &mut Self {
+        self.emit(Instruction::DupN(count))
+    }
+
+    pub fn swap(&mut self) -> &mut Self {
+        self.emit(Instruction::Swap)
+    }
+
+    pub fn over(&mut self) -> &mut Self {
+        self.emit(Instruction::Over)
+    }
+
+    pub fn pick(&mut self, depth: u8) -> &mut Self {
+        self.emit(Instruction::Pick(depth))
+    }
+
+    pub fn rot(&mut self, count: u8) -> &mut Self {
+        self.emit(Instruction::Rot(count))
+    }
+
+    pub fn depth(&mut self) -> &mut Self {
+        self.emit(Instruction::Depth)
+    }
+
+    pub fn clear_stack(&mut self) -> &mut Self {
+        self.emit(Instruction::ClearStack)
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.emit(Instruction::Nop)
+    }
+
+    pub fn store(&mut self, slot: u8) -> &mut Self {
+        self.emit(Instruction::Store(slot))
+    }
+
+    pub fn load(&mut self, slot: u8) -> &mut Self {
+        self.emit(Instruction::Load(slot))
+    }
+
+    pub fn set_local(&mut self, slot: u8, value: u8) -> &mut Self {
+        self.emit(Instruction::SetLocal(slot, value))
+    }
+
+    pub fn store_arg(&mut self, slot: u8) -> &mut Self {
+        self.emit(Instruction::StoreArg(slot))
+    }
+
+    pub fn load_arg(&mut self, slot: u8) -> &mut Self {
+        self.emit(Instruction::LoadArg(slot))
+    }
+
+    pub fn mem_store(&mut self) -> &mut Self {
+        self.emit(Instruction::MemStore)
+    }
+
+    pub fn mem_load(&mut self) -> &mut Self {
+        self.emit(Instruction::MemLoad)
+    }
+
+    pub fn add(&mut self) -> &mut Self {
+        self.emit(Instruction::Add)
+    }
+
+    pub fn sub(&mut self) -> &mut Self {
+        self.emit(Instruction::Sub)
+    }
+
+    pub fn mul(&mut self) -> &mut Self {
+        self.emit(Instruction::Mul)
+    }
+
+    pub fn div(&mut self) -> &mut Self {
+        self.emit(Instruction::Div)
+    }
+
+    pub fn modulo(&mut self) -> &mut Self {
+        self.emit(Instruction::Mod)
+    }
+
+    pub fn neg(&mut self) -> &mut Self {
+        self.emit(Instruction::Neg)
+    }
+
+    pub fn inc(&mut self) -> &mut Self {
+        self.emit(Instruction::Inc)
+    }
+
+    pub fn dec(&mut self) -> &mut Self {
+        self.emit(Instruction::Dec)
+    }
+
+    pub fn add_carry(&mut self) -> &mut Self {
+        self.emit(Instruction::AddCarry)
+    }
+
+    pub fn sub_borrow(&mut self) -> &mut Self {
+        self.emit(Instruction::SubBorrow)
+    }
+
+    pub fn add_i(&mut self) -> &mut Self {
+        self.emit(Instruction::AddI)
+    }
+
+    pub fn sub_i(&mut self) -> &mut Self {
+        self.emit(Instruction::SubI)
+    }
+
+    pub fn mul_i(&mut self) -> &mut Self {
+        self.emit(Instruction::MulI)
+    }
+
+    pub fn div_i(&mut self) -> &mut Self {
+        self.emit(Instruction::DivI)
+    }
+
+    pub fn mod_i(&mut self) -> &mut Self {
+        self.emit(Instruction::ModI)
+    }
+
+    pub fn push_i16(&mut self, value: u16) -> &mut Self {
+        self.emit(Instruction::PushI16(value))
+    }
+
+    pub fn add_i16(&mut self) -> &mut Self {
+        self.emit(Instruction::AddI16)
+    }
+
+    pub fn sub_i16(&mut self) -> &mut Self {
+        self.emit(Instruction::SubI16)
+    }
+
+    pub fn lt(&mut self) -> &mut Self {
+        self.emit(Instruction::Lt)
+    }
+
+    pub fn gt(&mut self) -> &mut Self {
+        self.emit(Instruction::Gt)
+    }
+
+    pub fn eq(&mut self) -> &mut Self {
+        self.emit(Instruction::Eq)
+    }
+
+    pub fn cmp(&mut self) -> &mut Self {
+        self.emit(Instruction::Cmp)
+    }
+
+    pub fn min(&mut self) -> &mut Self {
+        self.emit(Instruction::Min)
+    }
+
+    pub fn max(&mut self) -> &mut Self {
+        self.emit(Instruction::Max)
+    }
+
+    pub fn to_bool(&mut self) -> &mut Self {
+        self.emit(Instruction::ToBool)
+    }
+
+    pub fn to_upper(&mut self) -> &mut Self {
+        self.emit(Instruction::ToUpper)
+    }
+
+    pub fn to_lower(&mut self) -> &mut Self {
+        self.emit(Instruction::ToLower)
+    }
+
+    pub fn and(&mut self) -> &mut Self {
+        self.emit(Instruction::And)
+    }
+
+    pub fn or(&mut self) -> &mut Self {
+        self.emit(Instruction::Or)
+    }
+
+    pub fn xor(&mut self) -> &mut Self {
+        self.emit(Instruction::Xor)
+    }
+
+    pub fn not(&mut self) -> &mut Self {
+        self.emit(Instruction::Not)
+    }
+
+    pub fn shl(&mut self) -> &mut Self {
+        self.emit(Instruction::Shl)
+    }
+
+    pub fn shr(&mut self) -> &mut Self {
+        self.emit(Instruction::Shr)
+    }
+
+    pub fn jmp_eq(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpEq(target))
+    }
+
+    pub fn jmp_neq(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpNeq(target))
+    }
+
+    pub fn jmp_lt(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpLt(target))
+    }
+
+    pub fn jmp_gt(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpGt(target))
+    }
+
+    pub fn jmp_le(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpLe(target))
+    }
+
+    pub fn jmp_ge(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpGe(target))
+    }
+
+    pub fn jmp(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::Jmp(target))
+    }
+
+    pub fn jmp_overflow(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpOverflow(target))
+    }
+
+    pub fn jmp_if(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpIf(target))
+    }
+
+    pub fn jmp_if_not(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::JmpIfNot(target))
+    }
+
+    pub fn jmp_rel(&mut self, offset: i16) -> &mut Self {
+        self.emit(Instruction::JmpRel(offset))
+    }
+
+    pub fn jmp_eq_rel(&mut self, offset: i16) -> &mut Self {
+        self.emit(Instruction::JmpEqRel(offset))
+    }
+
+    pub fn jmp_neq_rel(&mut self, offset: i16) -> &mut Self {
+        self.emit(Instruction::JmpNeqRel(offset))
+    }
+
+    pub fn jmp_if_rel(&mut self, offset: i16) -> &mut Self {
+        self.emit(Instruction::JmpIfRel(offset))
+    }
+
+    pub fn jmp_if_not_rel(&mut self, offset: i16) -> &mut Self {
+        self.emit(Instruction::JmpIfNotRel(offset))
+    }
+
+    pub fn call(&mut self, target: usize) -> &mut Self {
+        self.emit(Instruction::Call(target))
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.emit(Instruction::Ret)
+    }
+
+    pub fn to_r(&mut self) -> &mut Self {
+        self.emit(Instruction::ToR)
+    }
+
+    pub fn from_r(&mut self) -> &mut Self {
+        self.emit(Instruction::FromR)
+    }
+
+    pub fn std_call(&mut self, func: StdFunc) -> &mut Self {
+        self.emit(Instruction::StdCall(func as usize))
+    }
+
+    pub fn assert(&mut self, expected: u8) -> &mut Self {
+        self.emit(Instruction::Assert(expected))
+    }
+
+    pub fn halt(&mut self, code: u8) -> &mut Self {
+        self.emit(Instruction::Halt(code))
+    }
+
+    pub fn interupt(&mut self) -> &mut Self {
+        self.emit(Instruction::Interupt)
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        self.labels.insert(name.to_string(), self.len());
+        self
+    }
+
+    // Emits a placeholder jump, labels can be defined after it as long as `finalize` runs once the program is built
+    pub fn jmp_to(&mut self, name: &str) -> &mut Self {
+        self.fixups.push((self.len(), name.to_string()));
+        self.emit(Instruction::Jmp(0))
+    }
+
+    pub fn finalize(&mut self) -> Result<(), ValidationError> {
+        if let Some(ProgramFull { max_len }) = self.full {
+            return Err(ValidationError::ProgramFull { max_len });
+        }
+        for (index, label) in &self.fixups {
+            let target = *self.labels.get(label).ok_or_else(|| ValidationError::UndefinedLabel { index: *index, label: label.clone() })?;
+            self.instructions[*index] = Instruction::Jmp(target);
+        }
+        self.fixups.clear();
+        Ok(())
+    }
+
+    // `cond` must leave one value on the stack, `body` runs for as long as that value is non-zero
+    pub fn while_loop(&mut self, cond: impl FnOnce(&mut Program), body: impl FnOnce(&mut Program)) -> &mut Self {
+        let start = self.len();
+        cond(self);
+        let exit = self.len();
+        self.jmp_if_not(0);
+        body(self);
+        self.jmp(start);
+        // The exit jump is missing when the program filled up before it
+        let end = self.len();
+        if let Some(instruction) = self.instructions.get_mut(exit) {
+            *instruction = Instruction::JmpIfNot(end);
+        }
+        self
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn builder_emits_the_loop() {
+        let mut program = Program::new();
+        program
+            .push_u8(10)
+            .push_u8(0)
+            .jmp_eq(6)
+            .push_u8(1)
+            .add()
+            .jmp(2);
+        assert_eq!(program.as_slice(), &[
+            Instruction::Push(10),
+            Instruction::Push(0),
+            Instruction::JmpEq(6),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::Jmp(2),
+        ]);
+        let mut vm = crate::VM::new();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[10, 10]);
+    }
+
+    #[test]
+    fn builder_helpers_map_to_instructions() {
+        let mut program = Program::new();
+        program.push_str("hi").std_call(StdFunc::PrintString).halt(3);
+        assert_eq!(program.as_slice(), &[Instruction::PushStr("hi".to_string()), Instruction::StdCall(2), Instruction::Halt(3)]);
+    }
+
+    #[test]
+    fn labels_resolve_on_finalize() {
+        // Counts local 0 down from 3, the backward jump goes through a label
+        let mut program = Program::new();
+        program.push_u8(3).store(0).label("loop").load(0).jmp_if_not(8).load(0).dec().store(0).jmp_to("loop");
+        program.load(0);
+        program.finalize().unwrap();
+        assert_eq!(program.get(7), Instruction::Jmp(2));
+        let mut vm = crate::VM::new();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[0]);
+    }
+
+    #[test]
+    fn forward_labels_resolve_on_finalize() {
+        let mut program = Program::new();
+        program.jmp_to("end");
+        assert_eq!(program.finalize(), Err(ValidationError::UndefinedLabel { index: 0, label: "end".to_string() }));
+        program.label("end").finalize().unwrap();
+        assert_eq!(program.get(0), Instruction::Jmp(1));
+    }
+
+    #[test]
+    fn unfinalized_labels_fail_validation() {
+        let mut program = Program::new();
+        program.jmp_to("nowhere");
+        assert_eq!(program.validate(), Err(ValidationError::UndefinedLabel { index: 0, label: "nowhere".to_string() }));
+        program.label("nowhere");
+        assert_eq!(program.validate(), Err(ValidationError::UnresolvedLabel { index: 0, label: "nowhere".to_string() }));
+        program.finalize().unwrap();
+        assert_eq!(program.validate(), Ok(()));
+    }
+
+    #[test]
+    fn while_loop_counts_to_ten() {
+        let mut program = Program::new();
+        program.push_u8(0).store(0)
+            .while_loop(|p| { p.push_u8(10).load(0).lt(); }, |p| { p.load(0).inc().store(0); })
+            .load(0);
+        assert_eq!(program.validate(), Ok(()));
+        let mut vm = crate::VM::new();
+        vm.execute_limited(&program, 1000).unwrap();
+        assert_eq!(vm.stack_slice(), &[10]);
+    }
+
+    #[test]
+    fn full_programs_fail_to_finalize() {
+        let mut program = Program::with_max_len(1);
+        program.nop().nop();
+        assert_eq!(program.len(), 1);
+        assert_eq!(program.validate(), Err(ValidationError::ProgramFull { max_len: 1 }));
+        assert_eq!(program.finalize(), Err(ValidationError::ProgramFull { max_len: 1 }));
+    }
+
+    #[test]
+    fn while_loop_survives_a_full_program() {
+        let mut program = Program::with_max_len(2);
+        program.while_loop(|p| { p.push_u8(1); }, |p| { p.nop(); });
+        assert_eq!(program.finalize(), Err(ValidationError::ProgramFull { max_len: 2 }));
+    }
+}
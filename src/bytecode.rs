@@ -0,0 +1,311 @@
+use std::fmt::Display;
+
+use crate::{Instruction, Opcode, Program, VmError, VmErrorKind, VM};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode { offset: usize, opcode: u8 },
+    UnexpectedEnd { offset: usize },
+    InvalidString { offset: usize },
+    InvalidAddress { offset: usize },
+    InvalidChar { offset: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode { offset, opcode } => write!(f, "unknown opcode 0x{:02x} at byte {}", opcode, offset),
+            DecodeError::UnexpectedEnd { offset } => write!(f, "unexpected end of bytecode at byte {}", offset),
+            DecodeError::InvalidString { offset } => write!(f, "invalid utf-8 string at byte {}", offset),
+            DecodeError::InvalidAddress { offset } => write!(f, "address out of range at byte {}", offset),
+            DecodeError::InvalidChar { offset } => write!(f, "invalid char at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Every instruction is a one-byte opcode followed by its operands, all little-endian:
+// 16-bit values and relative offsets are two bytes, addresses and std call ids are u64, chars are their u32 code point, strings and
+// byte lists are a u32 length followed by the bytes
+fn encode(instruction: &Instruction, output: &mut Vec<u8>) {
+    output.push(instruction.opcode() as u8);
+    match instruction {
+        Instruction::Push(value)
+        | Instruction::Store(value)
+        | Instruction::Load(value)
+        | Instruction::StoreArg(value)
+        | Instruction::LoadArg(value)
+        | Instruction::Pick(value)
+        | Instruction::DropN(value)
+        | Instruction::Rot(value)
+        | Instruction::Assert(value)
+        | Instruction::DupN(value)
+        | Instruction::Halt(value) => output.push(*value),
+        Instruction::PushStr(value) => {
+            output.extend((value.len() as u32).to_le_bytes());
+            output.extend(value.as_bytes());
+        },
+        Instruction::PushChar(value) => output.extend((*value as u32).to_le_bytes()),
+        Instruction::PushAll(values) => {
+            output.extend((values.len() as u32).to_le_bytes());
+            output.extend(values);
+        },
+        Instruction::PushI16(value) => output.extend(value.to_le_bytes()),
+        Instruction::JmpEq(location)
+        | Instruction::JmpNeq(location)
+        | Instruction::JmpLt(location)
+        | Instruction::JmpGt(location)
+        | Instruction::JmpLe(location)
+        | Instruction::JmpGe(location)
+        | Instruction::Jmp(location)
+        | Instruction::JmpOverflow(location)
+        | Instruction::JmpIf(location)
+        | Instruction::JmpIfNot(location)
+        | Instruction::Call(location)
+        | Instruction::StdCall(location) => output.extend((*location as u64).to_le_bytes()),
+        Instruction::PushCountedStr(value) => {
+            output.extend((value.len() as u32).to_le_bytes());
+            output.extend(value.as_bytes());
+        },
+        Instruction::JmpRel(offset)
+        | Instruction::JmpEqRel(offset)
+        | Instruction::JmpNeqRel(offset)
+        | Instruction::JmpIfRel(offset)
+        | Instruction::JmpIfNotRel(offset) => output.extend(offset.to_le_bytes()),
+        Instruction::SetLocal(slot, value) => output.extend([*slot, *value]),
+        _ => {},
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.offset.checked_add(count).filter(|end| *end <= self.data.len());
+        let end = end.ok_or(DecodeError::UnexpectedEnd { offset: self.data.len() })?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, DecodeError> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn address(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.offset;
+        let bytes = self.take(8)?;
+        let value = u64::from_le_bytes(bytes.try_into().unwrap());
+        usize::try_from(value).map_err(|_| DecodeError::InvalidAddress { offset })
+    }
+
+    fn char(&mut self) -> Result<char, DecodeError> {
+        let offset = self.offset;
+        let value = u32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        char::from_u32(value).ok_or(DecodeError::InvalidChar { offset })
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let length = u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let offset = self.offset + 4;
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString { offset })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let offset = self.offset;
+        let opcode = self.u8()?;
+        let opcode = Opcode::try_from(opcode).map_err(|opcode| DecodeError::UnknownOpcode { offset, opcode })?;
+        let instruction = match opcode {
+            Opcode::Push => Instruction::Push(self.u8()?),
+            Opcode::PushStr => Instruction::PushStr(self.string()?),
+            Opcode::Pop => Instruction::Pop,
+            Opcode::Dup => Instruction::Dup,
+            Opcode::Swap => Instruction::Swap,
+            Opcode::Over => Instruction::Over,
+            Opcode::Add => Instruction::Add,
+            Opcode::Sub => Instruction::Sub,
+            Opcode::Mul => Instruction::Mul,
+            Opcode::Div => Instruction::Div,
+            Opcode::Mod => Instruction::Mod,
+            Opcode::AddI => Instruction::AddI,
+            Opcode::SubI => Instruction::SubI,
+            Opcode::MulI => Instruction::MulI,
+            Opcode::DivI => Instruction::DivI,
+            Opcode::Lt => Instruction::Lt,
+            Opcode::Gt => Instruction::Gt,
+            Opcode::Eq => Instruction::Eq,
+            Opcode::And => Instruction::And,
+            Opcode::Or => Instruction::Or,
+            Opcode::Xor => Instruction::Xor,
+            Opcode::Not => Instruction::Not,
+            Opcode::Shl => Instruction::Shl,
+            Opcode::Shr => Instruction::Shr,
+            Opcode::JmpEq => Instruction::JmpEq(self.address()?),
+            Opcode::JmpNeq => Instruction::JmpNeq(self.address()?),
+            Opcode::Jmp => Instruction::Jmp(self.address()?),
+            Opcode::JmpOverflow => Instruction::JmpOverflow(self.address()?),
+            Opcode::Call => Instruction::Call(self.address()?),
+            Opcode::Ret => Instruction::Ret,
+            Opcode::StdCall => Instruction::StdCall(self.address()?),
+            Opcode::Interupt => Instruction::Interupt,
+            Opcode::Halt => Instruction::Halt(self.u8()?),
+            Opcode::Store => Instruction::Store(self.u8()?),
+            Opcode::Load => Instruction::Load(self.u8()?),
+            Opcode::PushI16 => Instruction::PushI16(self.u16()?),
+            Opcode::AddI16 => Instruction::AddI16,
+            Opcode::SubI16 => Instruction::SubI16,
+            Opcode::Neg => Instruction::Neg,
+            Opcode::Min => Instruction::Min,
+            Opcode::Max => Instruction::Max,
+            Opcode::Depth => Instruction::Depth,
+            Opcode::ClearStack => Instruction::ClearStack,
+            Opcode::PushAll => Instruction::PushAll(self.bytes()?.to_vec()),
+            Opcode::Rot => Instruction::Rot(self.u8()?),
+            Opcode::JmpLt => Instruction::JmpLt(self.address()?),
+            Opcode::JmpGt => Instruction::JmpGt(self.address()?),
+            Opcode::JmpLe => Instruction::JmpLe(self.address()?),
+            Opcode::JmpGe => Instruction::JmpGe(self.address()?),
+            Opcode::ToBool => Instruction::ToBool,
+            Opcode::JmpIf => Instruction::JmpIf(self.address()?),
+            Opcode::JmpIfNot => Instruction::JmpIfNot(self.address()?),
+            Opcode::MemStore => Instruction::MemStore,
+            Opcode::MemLoad => Instruction::MemLoad,
+            Opcode::Pick => Instruction::Pick(self.u8()?),
+            Opcode::DropN => Instruction::DropN(self.u8()?),
+            Opcode::Inc => Instruction::Inc,
+            Opcode::Dec => Instruction::Dec,
+            Opcode::PushChar => Instruction::PushChar(self.char()?),
+            Opcode::Nop => Instruction::Nop,
+            Opcode::StoreArg => Instruction::StoreArg(self.u8()?),
+            Opcode::LoadArg => Instruction::LoadArg(self.u8()?),
+            Opcode::AddCarry => Instruction::AddCarry,
+            Opcode::SubBorrow => Instruction::SubBorrow,
+            Opcode::Cmp => Instruction::Cmp,
+            Opcode::Assert => Instruction::Assert(self.u8()?),
+            Opcode::ModI => Instruction::ModI,
+            Opcode::PushCountedStr => Instruction::PushCountedStr(self.string()?),
+            Opcode::DupN => Instruction::DupN(self.u8()?),
+            Opcode::ToR => Instruction::ToR,
+            Opcode::FromR => Instruction::FromR,
+            Opcode::JmpRel => Instruction::JmpRel(self.i16()?),
+            Opcode::JmpEqRel => Instruction::JmpEqRel(self.i16()?),
+            Opcode::JmpNeqRel => Instruction::JmpNeqRel(self.i16()?),
+            Opcode::JmpIfRel => Instruction::JmpIfRel(self.i16()?),
+            Opcode::JmpIfNotRel => Instruction::JmpIfNotRel(self.i16()?),
+            Opcode::SetLocal => Instruction::SetLocal(self.u8()?, self.u8()?),
+            Opcode::ToUpper => Instruction::ToUpper,
+            Opcode::ToLower => Instruction::ToLower,
+        };
+        Ok(instruction)
+    }
+
+}
+
+impl Program {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        for instruction in &self.instructions {
+            encode(instruction, &mut output);
+        }
+        output
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Program, DecodeError> {
+        let mut decoder = Decoder { data, offset: 0 };
+        let mut instructions = Vec::new();
+        while decoder.offset < data.len() {
+            instructions.push(decoder.instruction()?);
+        }
+        Ok(Program::from(instructions))
+    }
+
+}
+
+impl VM {
+
+    // Decodes and runs untrusted bytecode with no input and discarded output, so any bytes can be fed in
+    pub fn run_bytes(data: &[u8], max_steps: usize) -> Result<(), VmError> {
+        let program = Program::from_bytes(data).map_err(|error| VmError { kind: VmErrorKind::Decode(error), at: 0 })?;
+        let mut vm = VM {
+            out: Box::new(std::io::sink()),
+            ..VM::with_input(Box::new(std::io::empty()))
+        };
+        vm.execute_limited(&program, max_steps)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let program = Program::from(vec![
+            Instruction::Push(10),
+            Instruction::PushStr("Hello, World!\n".to_string()),
+            Instruction::StdCall(2),
+            Instruction::JmpEq(0),
+            Instruction::Add,
+            Instruction::Interupt,
+        ]);
+        assert_eq!(Program::from_bytes(&program.to_bytes()), Ok(program));
+    }
+
+    #[test]
+    fn unknown_opcodes_are_rejected() {
+        assert_eq!(Program::from_bytes(&[0xff]), Err(DecodeError::UnknownOpcode { offset: 0, opcode: 0xff }));
+    }
+
+    #[test]
+    fn truncated_operands_are_rejected() {
+        let mut data = Program::from(vec![Instruction::Add, Instruction::PushStr("abc".to_string())]).to_bytes();
+        data.pop();
+        assert!(matches!(Program::from_bytes(&data), Err(DecodeError::UnexpectedEnd { .. })));
+        assert_eq!(Program::from_bytes(&[Instruction::Push(0).opcode() as u8]), Err(DecodeError::UnexpectedEnd { offset: 1 }));
+    }
+
+    #[test]
+    fn run_bytes_never_panics_on_garbage() {
+        let mut seed = 0x1234_5678u32;
+        for len in 0..2000usize {
+            let mut data = Vec::new();
+            for _ in 0..len % 64 {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                data.push((seed % 0x50) as u8);
+            }
+            let _ = VM::run_bytes(&data, 1000);
+        }
+    }
+
+    #[test]
+    fn run_bytes_reports_decode_and_runtime_errors() {
+        assert!(matches!(VM::run_bytes(&[0xff], 10).map_err(|error| error.kind), Err(VmErrorKind::Decode(_))));
+        assert_eq!(VM::run_bytes(&[Opcode::Pop as u8], 10).map_err(|error| error.kind), Err(VmErrorKind::StackUnderflow));
+        let jump_to_self = Program::from(vec![Instruction::Jmp(0)]).to_bytes();
+        assert_eq!(VM::run_bytes(&jump_to_self, 10).map_err(|error| error.kind), Err(VmErrorKind::StepLimitExceeded));
+        assert_eq!(VM::run_bytes(&[], 10), Ok(()));
+    }
+}
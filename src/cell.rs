@@ -0,0 +1,138 @@
+use std::fmt::{Binary, Debug, Display, LowerHex, Octal};
+use std::ops::{BitAnd, BitOr, BitXor, Div, Not, Rem};
+use std::str::FromStr;
+
+// A value that fits in one stack slot, local or memory cell
+pub trait Cell:
+    Copy
+    + Default
+    + Ord
+    + Debug
+    + Display
+    + LowerHex
+    + Octal
+    + Binary
+    + FromStr
+    + Div<Output = Self>
+    + Rem<Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn from_u8(value: u8) -> Self;
+
+    // Keeps the low byte, used wherever a cell is read as a character
+    fn as_u8(self) -> u8;
+
+    fn to_usize(self) -> usize;
+
+    // Lossless for every cell type, used to report a cell outside the VM
+    fn to_u64(self) -> u64;
+
+    // Values that do not fit are clamped to the largest cell value
+    fn from_usize(value: usize) -> Self;
+
+    fn from_bool(value: bool) -> Self {
+        if value { Self::ONE } else { Self::ZERO }
+    }
+
+    fn overflowing_add(self, rhs: Self) -> (Self, bool);
+    fn overflowing_sub(self, rhs: Self) -> (Self, bool);
+    fn overflowing_mul(self, rhs: Self) -> (Self, bool);
+    fn wrapping_neg(self) -> Self;
+    fn checked_shl(self, amount: u32) -> Option<Self>;
+    fn checked_shr(self, amount: u32) -> Option<Self>;
+
+    // Signed variants reinterpret the bits as the two's complement type of the same width
+    fn signed_overflowing_add(self, rhs: Self) -> (Self, bool);
+    fn signed_overflowing_sub(self, rhs: Self) -> (Self, bool);
+    fn signed_overflowing_mul(self, rhs: Self) -> (Self, bool);
+    fn signed_overflowing_div(self, rhs: Self) -> (Self, bool);
+    fn signed_overflowing_rem(self, rhs: Self) -> (Self, bool);
+}
+
+macro_rules! impl_cell {
+    ($unsigned:ty, $signed:ty) => {
+        impl Cell for $unsigned {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+
+            fn from_u8(value: u8) -> Self {
+                value as Self
+            }
+
+            fn as_u8(self) -> u8 {
+                self as u8
+            }
+
+            fn to_usize(self) -> usize {
+                self as usize
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_usize(value: usize) -> Self {
+                Self::try_from(value).unwrap_or(Self::MAX)
+            }
+
+            fn overflowing_add(self, rhs: Self) -> (Self, bool) {
+                <$unsigned>::overflowing_add(self, rhs)
+            }
+
+            fn overflowing_sub(self, rhs: Self) -> (Self, bool) {
+                <$unsigned>::overflowing_sub(self, rhs)
+            }
+
+            fn overflowing_mul(self, rhs: Self) -> (Self, bool) {
+                <$unsigned>::overflowing_mul(self, rhs)
+            }
+
+            fn wrapping_neg(self) -> Self {
+                <$unsigned>::wrapping_neg(self)
+            }
+
+            fn checked_shl(self, amount: u32) -> Option<Self> {
+                <$unsigned>::checked_shl(self, amount)
+            }
+
+            fn checked_shr(self, amount: u32) -> Option<Self> {
+                <$unsigned>::checked_shr(self, amount)
+            }
+
+            fn signed_overflowing_add(self, rhs: Self) -> (Self, bool) {
+                let (value, overflow) = (self as $signed).overflowing_add(rhs as $signed);
+                (value as Self, overflow)
+            }
+
+            fn signed_overflowing_sub(self, rhs: Self) -> (Self, bool) {
+                let (value, overflow) = (self as $signed).overflowing_sub(rhs as $signed);
+                (value as Self, overflow)
+            }
+
+            fn signed_overflowing_mul(self, rhs: Self) -> (Self, bool) {
+                let (value, overflow) = (self as $signed).overflowing_mul(rhs as $signed);
+                (value as Self, overflow)
+            }
+
+            fn signed_overflowing_div(self, rhs: Self) -> (Self, bool) {
+                let (value, overflow) = (self as $signed).overflowing_div(rhs as $signed);
+                (value as Self, overflow)
+            }
+
+            fn signed_overflowing_rem(self, rhs: Self) -> (Self, bool) {
+                let (value, overflow) = (self as $signed).overflowing_rem(rhs as $signed);
+                (value as Self, overflow)
+            }
+        }
+    };
+}
+
+impl_cell!(u8, i8);
+impl_cell!(u16, i16);
+impl_cell!(u32, i32);
@@ -0,0 +1,3415 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+mod analysis;
+mod asm;
+mod builder;
+mod bytecode;
+mod cell;
+mod opcode;
+mod profile;
+
+pub use analysis::{AnalysisError, StackAnalysis};
+pub use asm::AsmError;
+pub use bytecode::DecodeError;
+pub use cell::Cell;
+pub use opcode::Opcode;
+pub use profile::ProfileReport;
+
+const STACK_SIZE: usize = 128;
+const MAX_CALL_DEPTH: usize = 64;
+const LOCALS_SIZE: usize = 16;
+const MEMORY_SIZE: usize = 256;
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+const EVAL_STEP_LIMIT: usize = 1_000_000;
+const REPORT_STACK_VALUES: usize = 8;
+const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction<T = u8> {
+    Push(T),
+    PushStr(String),
+    PushCountedStr(String),
+    PushChar(char),
+    PushAll(Vec<T>),
+    Pop,
+    DropN(u8),
+    Dup,
+    DupN(u8),
+    Swap,
+    Over,
+    Pick(u8),
+    Rot(u8),
+    Depth,
+    ClearStack,
+    Nop,
+    Store(u8),
+    Load(u8),
+    SetLocal(u8, T),
+    StoreArg(u8),
+    LoadArg(u8),
+    MemStore,
+    MemLoad,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Neg,
+    Inc,
+    Dec,
+    AddCarry,
+    SubBorrow,
+    AddI,
+    SubI,
+    MulI,
+    DivI,
+    ModI,
+    PushI16(u16),
+    AddI16,
+    SubI16,
+    Lt,
+    Gt,
+    Eq,
+    Cmp,
+    Min,
+    Max,
+    ToBool,
+    ToUpper,
+    ToLower,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    JmpEq(usize),
+    JmpNeq(usize),
+    JmpLt(usize),
+    JmpGt(usize),
+    JmpLe(usize),
+    JmpGe(usize),
+    Jmp(usize),
+    JmpOverflow(usize),
+    JmpIf(usize),
+    JmpIfNot(usize),
+    JmpRel(i16),
+    JmpEqRel(i16),
+    JmpNeqRel(i16),
+    JmpIfRel(i16),
+    JmpIfNotRel(i16),
+    Call(usize),
+    Ret,
+    ToR,
+    FromR,
+    StdCall(usize),
+    Assert(u8),
+    Halt(u8),
+    Interupt
+}
+
+impl<T> Instruction<T> {
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Push(_) => "push",
+            Instruction::PushStr(_) => "pushstr",
+            Instruction::PushCountedStr(_) => "pushcountedstr",
+            Instruction::PushChar(_) => "pushchar",
+            Instruction::PushAll(_) => "pushall",
+            Instruction::Pop => "pop",
+            Instruction::DropN(_) => "dropn",
+            Instruction::Dup => "dup",
+            Instruction::DupN(_) => "dupn",
+            Instruction::Swap => "swap",
+            Instruction::Over => "over",
+            Instruction::Pick(_) => "pick",
+            Instruction::Rot(_) => "rot",
+            Instruction::Depth => "depth",
+            Instruction::ClearStack => "clearstack",
+            Instruction::Nop => "nop",
+            Instruction::Store(_) => "store",
+            Instruction::Load(_) => "load",
+            Instruction::SetLocal(_, _) => "setlocal",
+            Instruction::StoreArg(_) => "storearg",
+            Instruction::LoadArg(_) => "loadarg",
+            Instruction::MemStore => "memstore",
+            Instruction::MemLoad => "memload",
+            Instruction::Add => "add",
+            Instruction::Sub => "sub",
+            Instruction::Mul => "mul",
+            Instruction::Div => "div",
+            Instruction::Mod => "mod",
+            Instruction::Neg => "neg",
+            Instruction::Inc => "inc",
+            Instruction::Dec => "dec",
+            Instruction::AddCarry => "addcarry",
+            Instruction::SubBorrow => "subborrow",
+            Instruction::AddI => "addi",
+            Instruction::SubI => "subi",
+            Instruction::MulI => "muli",
+            Instruction::DivI => "divi",
+            Instruction::ModI => "modi",
+            Instruction::PushI16(_) => "pushi16",
+            Instruction::AddI16 => "addi16",
+            Instruction::SubI16 => "subi16",
+            Instruction::Lt => "lt",
+            Instruction::Gt => "gt",
+            Instruction::Eq => "eq",
+            Instruction::Cmp => "cmp",
+            Instruction::Min => "min",
+            Instruction::Max => "max",
+            Instruction::ToBool => "tobool",
+            Instruction::ToUpper => "toupper",
+            Instruction::ToLower => "tolower",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            Instruction::Xor => "xor",
+            Instruction::Not => "not",
+            Instruction::Shl => "shl",
+            Instruction::Shr => "shr",
+            Instruction::JmpEq(_) => "jmpeq",
+            Instruction::JmpNeq(_) => "jmpneq",
+            Instruction::JmpLt(_) => "jmplt",
+            Instruction::JmpGt(_) => "jmpgt",
+            Instruction::JmpLe(_) => "jmple",
+            Instruction::JmpGe(_) => "jmpge",
+            Instruction::Jmp(_) => "jmp",
+            Instruction::JmpOverflow(_) => "jmpoverflow",
+            Instruction::JmpIf(_) => "jmpif",
+            Instruction::JmpIfNot(_) => "jmpifnot",
+            Instruction::JmpRel(_) => "jmprel",
+            Instruction::JmpEqRel(_) => "jmpeqrel",
+            Instruction::JmpNeqRel(_) => "jmpneqrel",
+            Instruction::JmpIfRel(_) => "jmpifrel",
+            Instruction::JmpIfNotRel(_) => "jmpifnotrel",
+            Instruction::Call(_) => "call",
+            Instruction::Ret => "ret",
+            Instruction::ToR => "tor",
+            Instruction::FromR => "fromr",
+            Instruction::StdCall(_) => "stdcall",
+            Instruction::Assert(_) => "assert",
+            Instruction::Halt(_) => "halt",
+            Instruction::Interupt => "interupt",
+        }
+    }
+
+    pub fn operand_count(&self) -> usize {
+        match self {
+            Instruction::Push(_)
+            | Instruction::PushStr(_)
+            | Instruction::PushCountedStr(_)
+            | Instruction::PushChar(_)
+            | Instruction::PushAll(_)
+            | Instruction::Store(_)
+            | Instruction::Load(_)
+            | Instruction::StoreArg(_)
+            | Instruction::LoadArg(_)
+            | Instruction::PushI16(_)
+            | Instruction::JmpEq(_)
+            | Instruction::JmpNeq(_)
+            | Instruction::JmpLt(_)
+            | Instruction::JmpGt(_)
+            | Instruction::JmpLe(_)
+            | Instruction::JmpGe(_)
+            | Instruction::Jmp(_)
+            | Instruction::JmpOverflow(_)
+            | Instruction::JmpIf(_)
+            | Instruction::JmpIfNot(_)
+            | Instruction::JmpRel(_)
+            | Instruction::JmpEqRel(_)
+            | Instruction::JmpNeqRel(_)
+            | Instruction::JmpIfRel(_)
+            | Instruction::JmpIfNotRel(_)
+            | Instruction::Call(_)
+            | Instruction::StdCall(_)
+            | Instruction::Assert(_)
+            | Instruction::Rot(_)
+            | Instruction::Pick(_)
+            | Instruction::DropN(_)
+            | Instruction::DupN(_)
+            | Instruction::Halt(_) => 1,
+            Instruction::SetLocal(_, _) => 2,
+            _ => 0,
+        }
+    }
+
+    // Fuel used by metered VMs, stopping is free so a program can always halt
+    pub fn cost(&self) -> u64 {
+        match self {
+            Instruction::PushStr(value)
+            | Instruction::PushCountedStr(value) => 1 + value.len() as u64,
+            Instruction::PushAll(values) => 1 + values.len() as u64,
+            Instruction::StdCall(_) => 10,
+            Instruction::Halt(_)
+            | Instruction::Interupt => 0,
+            _ => 1,
+        }
+    }
+
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Instruction::JmpEq(location)
+            | Instruction::JmpNeq(location)
+            | Instruction::JmpLt(location)
+            | Instruction::JmpGt(location)
+            | Instruction::JmpLe(location)
+            | Instruction::JmpGe(location)
+            | Instruction::Jmp(location)
+            | Instruction::JmpOverflow(location)
+            | Instruction::JmpIf(location)
+            | Instruction::JmpIfNot(location)
+            | Instruction::Call(location) => Some(*location),
+            _ => None,
+        }
+    }
+
+    pub fn relative_offset(&self) -> Option<i16> {
+        match self {
+            Instruction::JmpRel(offset)
+            | Instruction::JmpEqRel(offset)
+            | Instruction::JmpNeqRel(offset)
+            | Instruction::JmpIfRel(offset)
+            | Instruction::JmpIfNotRel(offset) => Some(*offset),
+            _ => None,
+        }
+    }
+
+    fn jump_target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Instruction::JmpEq(location)
+            | Instruction::JmpNeq(location)
+            | Instruction::JmpLt(location)
+            | Instruction::JmpGt(location)
+            | Instruction::JmpLe(location)
+            | Instruction::JmpGe(location)
+            | Instruction::Jmp(location)
+            | Instruction::JmpOverflow(location)
+            | Instruction::JmpIf(location)
+            | Instruction::JmpIfNot(location)
+            | Instruction::Call(location) => Some(location),
+            _ => None,
+        }
+    }
+
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VmErrorKind {
+    StackOverflow,
+    StackUnderflow,
+    DivideByZero,
+    InvalidJump(usize),
+    InvalidStdCall(usize),
+    CallStackOverflow,
+    CallStackUnderflow,
+    StepLimitExceeded,
+    EndOfInput,
+    InvalidInput,
+    Io(std::io::ErrorKind),
+    MissingStringTerminator,
+    InvalidLocal(u8),
+    InvalidSnapshot,
+    OutOfBoundsMemory(usize),
+    Decode(DecodeError),
+    Validation(ValidationError),
+    InvalidChar(char),
+    TimeoutExceeded,
+    AssertionFailed { expected: u8, got: u64 },
+    StringTooLong(usize),
+    OutOfFuel,
+    InvalidRelativeJump(i16),
+    Panic(String),
+}
+
+impl Display for VmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmErrorKind::StackOverflow => write!(f, "stack overflow"),
+            VmErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            VmErrorKind::DivideByZero => write!(f, "divide by zero"),
+            VmErrorKind::InvalidJump(location) => write!(f, "invalid jump to {}", location),
+            VmErrorKind::InvalidStdCall(id) => write!(f, "invalid std call {}", id),
+            VmErrorKind::CallStackOverflow => write!(f, "call stack overflow"),
+            VmErrorKind::CallStackUnderflow => write!(f, "call stack underflow"),
+            VmErrorKind::StepLimitExceeded => write!(f, "step limit exceeded"),
+            VmErrorKind::EndOfInput => write!(f, "end of input"),
+            VmErrorKind::InvalidInput => write!(f, "invalid input"),
+            VmErrorKind::Io(kind) => write!(f, "io error: {}", kind),
+            VmErrorKind::MissingStringTerminator => write!(f, "missing string terminator"),
+            VmErrorKind::InvalidLocal(slot) => write!(f, "invalid local {}", slot),
+            VmErrorKind::InvalidSnapshot => write!(f, "invalid snapshot"),
+            VmErrorKind::OutOfBoundsMemory(address) => write!(f, "out of bounds memory access at {}", address),
+            VmErrorKind::Decode(error) => write!(f, "decode error: {}", error),
+            VmErrorKind::Validation(error) => write!(f, "invalid program: {}", error),
+            VmErrorKind::InvalidChar(chr) => write!(f, "char {:?} does not fit in a byte", chr),
+            VmErrorKind::TimeoutExceeded => write!(f, "timeout exceeded"),
+            VmErrorKind::AssertionFailed { expected, got } => write!(f, "assertion failed: expected {}, got {}", expected, got),
+            VmErrorKind::StringTooLong(length) => write!(f, "string of {} bytes is too long", length),
+            VmErrorKind::OutOfFuel => write!(f, "out of fuel"),
+            VmErrorKind::InvalidRelativeJump(offset) => write!(f, "invalid relative jump by {}", offset),
+            VmErrorKind::Panic(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmError {
+    pub kind: VmErrorKind,
+    // Index of the instruction that was executing when the error happened
+    pub at: usize,
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error at instruction {}: {}", self.at, self.kind)
+    }
+}
+
+impl VmError {
+
+    // Something like `stack underflow at #4 (pop), stack: [01 02]`, showing only the top of the stack
+    pub fn report<T: Cell>(&self, vm: &VM<T>, program: &Program<T>) -> String {
+        let mut report = format!("{} at #{}", self.kind, self.at);
+        if let Some(instruction) = vm.last_instruction(program) {
+            report.push_str(&format!(" ({})", instruction));
+        }
+        let stack = vm.stack_slice();
+        let shown = &stack[stack.len().saturating_sub(REPORT_STACK_VALUES)..];
+        let values: Vec<String> = shown.iter().map(|value| format!("{:02x}", value)).collect();
+        let more = if shown.len() < stack.len() { ".. " } else { "" };
+        report.push_str(&format!(", stack: [{}{}]", more, values.join(" ")));
+        report
+    }
+
+}
+
+impl std::error::Error for VmError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    InvalidJump { index: usize, target: usize },
+    UndefinedLabel { index: usize, label: String },
+    InvalidRelativeJump { index: usize, offset: i16 },
+    // The label exists but `finalize` has not patched the jump yet
+    UnresolvedLabel { index: usize, label: String },
+    // The builder helpers ran into `max_len`, the instructions past it were dropped
+    ProgramFull { max_len: usize },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::InvalidJump { index, target } => write!(f, "instruction {} jumps to invalid target {}", index, target),
+            ValidationError::UndefinedLabel { index, label } => write!(f, "instruction {} jumps to undefined label '{}'", index, label),
+            ValidationError::InvalidRelativeJump { index, offset } => write!(f, "instruction {} jumps by {} out of the program", index, offset),
+            ValidationError::UnresolvedLabel { index, label } => write!(f, "instruction {} jumps to label '{}' before the program was finalized", index, label),
+            ValidationError::ProgramFull { max_len } => write!(f, "instructions were dropped past the limit of {}", max_len),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgramFull {
+    pub max_len: usize,
+}
+
+impl Display for ProgramFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "program is limited to {} instructions", self.max_len)
+    }
+}
+
+impl std::error::Error for ProgramFull {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtendError {
+    Full(ProgramFull),
+    // Both programs define the label, neither index can be kept
+    DuplicateLabel(String),
+}
+
+impl Display for ExtendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendError::Full(error) => write!(f, "{}", error),
+            ExtendError::DuplicateLabel(label) => write!(f, "label '{}' is defined in both programs", label),
+        }
+    }
+}
+
+impl std::error::Error for ExtendError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidRadix {
+    pub radix: u32,
+}
+
+impl Display for InvalidRadix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsupported print radix {}", self.radix)
+    }
+}
+
+impl std::error::Error for InvalidRadix {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program<T = u8> {
+    instructions: Vec<Instruction<T>>,
+    labels: HashMap<String, usize>,
+    // Jumps emitted before their label was known, patched by `finalize`
+    fixups: Vec<(usize, String)>,
+    max_len: Option<usize>,
+    // Set when a chained builder call could not emit its instruction
+    full: Option<ProgramFull>,
+}
+
+// The default constructors are only on u8 programs, a defaulted type parameter does not drive inference
+impl Program {
+
+    pub fn new() -> Self {
+        Self::with_cells()
+    }
+
+    // Pushing past `max` fails instead of growing the program
+    pub fn with_max_len(max: usize) -> Self {
+        Self {
+            max_len: Some(max),
+            ..Self::new()
+        }
+    }
+
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Cell> Program<T> {
+
+    // An empty program for any cell type, e.g. `Program::<u16>::with_cells()`
+    pub fn with_cells() -> Self {
+        Self {
+            instructions: Vec::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            max_len: None,
+            full: None,
+        }
+    }
+
+    pub fn push(&mut self, instruction: Instruction<T>) -> Result<(), ProgramFull> {
+        if let Some(max_len) = self.max_len.filter(|max_len| self.instructions.len() >= *max_len) {
+            return Err(ProgramFull { max_len });
+        }
+        self.instructions.push(instruction);
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> Instruction<T> {
+        fetch(&self.instructions, index)
+    }
+
+    // Replaces an already emitted instruction, used to backpatch forward jumps
+    #[allow(clippy::result_unit_err)]
+    pub fn set(&mut self, index: usize, instruction: Instruction<T>) -> Result<(), ()> {
+        *self.instructions.get_mut(index).ok_or(())? = instruction;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instruction<T>> {
+        self.instructions.iter()
+    }
+
+    pub fn as_slice(&self) -> &[Instruction<T>] {
+        &self.instructions
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    // Appends `other` with its jumps, labels and pending fixups shifted by the current length,
+    // nothing is appended when it does not fit or a label is defined in both programs
+    pub fn extend_from(&mut self, other: &Program<T>) -> Result<(), ExtendError> {
+        let offset = self.instructions.len();
+        if let Some(max_len) = self.max_len.filter(|max_len| offset + other.len() > *max_len) {
+            return Err(ExtendError::Full(ProgramFull { max_len }));
+        }
+        if let Some(label) = other.labels.keys().find(|label| self.labels.contains_key(*label)) {
+            return Err(ExtendError::DuplicateLabel(label.clone()));
+        }
+        for instruction in &other.instructions {
+            let mut instruction = instruction.clone();
+            if let Some(target) = instruction.jump_target_mut() {
+                *target += offset;
+            }
+            self.instructions.push(instruction);
+        }
+        for (label, index) in &other.labels {
+            self.labels.insert(label.clone(), index + offset);
+        }
+        for (index, label) in &other.fixups {
+            self.fixups.push((index + offset, label.clone()));
+        }
+        self.full = self.full.or(other.full);
+        Ok(())
+    }
+
+    // Same rule as the VM: a target right past the last instruction is valid and halts.
+    // Jumps still waiting on `finalize` are rejected, their placeholder target means nothing
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if let Some(ProgramFull { max_len }) = self.full {
+            return Err(ValidationError::ProgramFull { max_len });
+        }
+        if let Some((index, label)) = self.fixups.first() {
+            let (index, label) = (*index, label.clone());
+            if self.labels.contains_key(&label) {
+                return Err(ValidationError::UnresolvedLabel { index, label });
+            }
+            return Err(ValidationError::UndefinedLabel { index, label });
+        }
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if let Some(target) = instruction.jump_target() {
+                if target > self.instructions.len() {
+                    return Err(ValidationError::InvalidJump { index, target });
+                }
+            }
+            if let Some(offset) = instruction.relative_offset() {
+                if relative_target(index, offset).is_none_or(|target| target > self.instructions.len()) {
+                    return Err(ValidationError::InvalidRelativeJump { index, offset });
+                }
+            }
+        }
+        Ok(())
+    }
+
+}
+
+// Cells above 255 are not characters and are returned unchanged
+fn map_ascii<T: Cell>(value: T, map: impl FnOnce(&u8) -> u8) -> T {
+    let byte = value.as_u8();
+    if T::from_u8(byte) == value { T::from_u8(map(&byte)) } else { value }
+}
+
+// Where a relative jump at `index` lands, None when that is before the first instruction
+fn relative_target(index: usize, offset: i16) -> Option<usize> {
+    index.checked_add_signed(offset as isize)
+}
+
+// Reading past the last instruction yields Interupt, shared by programs and the raw slices the VM runs
+fn fetch<T: Clone>(code: &[Instruction<T>], index: usize) -> Instruction<T> {
+    code.get(index).cloned().unwrap_or(Instruction::Interupt)
+}
+
+impl From<Vec<Instruction>> for Program {
+    fn from(instructions: Vec<Instruction>) -> Self {
+        Self::from_instructions(instructions)
+    }
+}
+
+impl<T> From<Program<T>> for Vec<Instruction<T>> {
+    fn from(program: Program<T>) -> Self {
+        program.instructions
+    }
+}
+
+impl<T> AsRef<[Instruction<T>]> for Program<T> {
+    fn as_ref(&self) -> &[Instruction<T>] {
+        &self.instructions
+    }
+}
+
+// Instructions are appended as they are, use `extend_from` to relocate the jumps of another program.
+// Like the builder helpers, a full program drops the rest and `validate` or `finalize` reports it
+impl<T: Cell> Extend<Instruction<T>> for Program<T> {
+    fn extend<I: IntoIterator<Item = Instruction<T>>>(&mut self, iter: I) {
+        for instruction in iter {
+            if let Err(error) = self.push(instruction) {
+                self.full = Some(error);
+                break;
+            }
+        }
+    }
+}
+
+impl<T> FromIterator<Instruction<T>> for Program<T> {
+    fn from_iter<I: IntoIterator<Item = Instruction<T>>>(iter: I) -> Self {
+        Self::from_instructions(iter.into_iter().collect())
+    }
+}
+
+impl<T> Program<T> {
+
+    fn from_instructions(instructions: Vec<Instruction<T>>) -> Self {
+        Self {
+            instructions,
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            max_len: None,
+            full: None,
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StdFunc {
+    PrintU8 = 0x0,
+    PrintChar = 0x1,
+    PrintString = 0x2,
+    Clone = 0x3,
+    ReadU8 = 0x4,
+    ReadChar = 0x5,
+    PrintHex = 0x6,
+    PrintMemString = 0x7,
+    Checksum = 0x8,
+    PrintU8Ln = 0x9,
+    PrintStack = 0xA,
+    Cycles = 0xB,
+    Rand = 0xC,
+    PrintCountedString = 0xD,
+}
+
+impl StdFunc {
+
+    pub fn from_id(id: usize) -> Option<StdFunc> {
+        match id {
+            0x0 => Some(StdFunc::PrintU8),
+            0x1 => Some(StdFunc::PrintChar),
+            0x2 => Some(StdFunc::PrintString),
+            0x3 => Some(StdFunc::Clone),
+            0x4 => Some(StdFunc::ReadU8),
+            0x5 => Some(StdFunc::ReadChar),
+            0x6 => Some(StdFunc::PrintHex),
+            0x7 => Some(StdFunc::PrintMemString),
+            0x8 => Some(StdFunc::Checksum),
+            0x9 => Some(StdFunc::PrintU8Ln),
+            0xA => Some(StdFunc::PrintStack),
+            0xB => Some(StdFunc::Cycles),
+            0xC => Some(StdFunc::Rand),
+            0xD => Some(StdFunc::PrintCountedString),
+            _ => None,
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Continued,
+    Halted,
+    Jumped { to: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakReason {
+    Breakpoint(usize),
+    Halted,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VmSnapshot<T = u8> {
+    pub stack_pointer: usize,
+    pub program_pointer: usize,
+    pub overflow: bool,
+    pub stack: Vec<T>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StdCallRecord<T = u8> {
+    pub func: StdFunc,
+    // Popped values in the order they were popped
+    pub consumed: Vec<T>,
+    pub output: String,
+}
+
+pub struct VM<T = u8> {
+    stack: Vec<T>,
+    stack_pointer: usize,
+    program_pointer: usize,
+    overflow: bool,
+    // Unlike the overflow flag the carry survives until the next Add, Sub, AddCarry or SubBorrow,
+    // so the operands of the next byte can be brought up in between
+    carry: bool,
+    // Return address and the caller's frame pointer
+    call_stack: Vec<(usize, usize)>,
+    // Values moved off the data stack by ToR, they share `max_call_depth` with the call frames
+    return_values: Vec<T>,
+    frame_pointer: usize,
+    max_call_depth: usize,
+    input: Box<dyn Read>,
+    out: Box<dyn Write>,
+    exit_code: Option<u8>,
+    locals: [T; LOCALS_SIZE],
+    instruction_count: u64,
+    memory: Vec<T>,
+    records: Option<Vec<StdCallRecord<T>>>,
+    watermark: Option<usize>,
+    high_water_mark: usize,
+    // Index of the instruction, it is only cloned out of the program on request
+    last_instruction: Option<usize>,
+    // Remaining budget, each instruction uses up its `cost`
+    fuel: Option<u64>,
+    print_radix: u32,
+    // xorshift state used by the Rand std call, `reset` starts it over from the seed
+    seed: u64,
+    rng: u64,
+    // Where `execute_until` stopped, that instruction is not checked again when resuming
+    paused_at: Option<usize>,
+}
+
+pub type DefaultVm = VM<u8>;
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Like `Program::new`, `new` stays on u8 cells so untyped code keeps inferring
+impl VM {
+
+    pub fn new() -> Self {
+        Self::with_cells()
+    }
+
+}
+
+impl<T: Cell> VM<T> {
+
+    pub fn with_cells() -> Self {
+        Self::with_stack_size(STACK_SIZE)
+    }
+
+    pub fn with_stack_size(size: usize) -> Self {
+        Self {
+            stack: vec![T::ZERO; size],
+            stack_pointer: 0,
+            program_pointer: 0,
+            overflow: false,
+            carry: false,
+            call_stack: Vec::new(),
+            return_values: Vec::new(),
+            frame_pointer: 0,
+            max_call_depth: MAX_CALL_DEPTH,
+            input: Box::new(std::io::stdin()),
+            out: Box::new(std::io::stdout()),
+            exit_code: None,
+            locals: [T::ZERO; LOCALS_SIZE],
+            instruction_count: 0,
+            memory: vec![T::ZERO; MEMORY_SIZE],
+            records: None,
+            watermark: None,
+            high_water_mark: 0,
+            last_instruction: None,
+            fuel: None,
+            print_radix: 10,
+            seed: DEFAULT_SEED,
+            rng: DEFAULT_SEED,
+            paused_at: None,
+        }
+    }
+
+    pub fn with_memory(size: usize) -> Self {
+        Self {
+            memory: vec![T::ZERO; size],
+            ..Self::with_cells()
+        }
+    }
+
+    pub fn with_input(input: Box<dyn Read>) -> Self {
+        Self {
+            input,
+            ..Self::with_cells()
+        }
+    }
+
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        Self {
+            out,
+            ..Self::with_cells()
+        }
+    }
+
+    // Std calls still write to the output, they are also logged in `records`
+    pub fn with_recording() -> Self {
+        Self {
+            records: Some(Vec::new()),
+            ..Self::with_cells()
+        }
+    }
+
+    pub fn with_fuel(fuel: u64) -> Self {
+        Self {
+            fuel: Some(fuel),
+            ..Self::with_cells()
+        }
+    }
+
+    // xorshift never leaves zero, a zero seed falls back to the default one
+    pub fn with_seed(seed: u64) -> Self {
+        let seed = if seed == 0 { DEFAULT_SEED } else { seed };
+        Self {
+            seed,
+            rng: seed,
+            ..Self::with_cells()
+        }
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    // Base used by PrintU8 and PrintU8Ln, one of 2, 8, 10 or 16
+    pub fn set_print_radix(&mut self, radix: u32) -> Result<(), InvalidRadix> {
+        if ![2, 8, 10, 16].contains(&radix) {
+            return Err(InvalidRadix { radix });
+        }
+        self.print_radix = radix;
+        Ok(())
+    }
+
+    // A soft limit below the stack size, see `watermark_exceeded`
+    pub fn set_watermark(&mut self, depth: usize) {
+        self.watermark = Some(depth);
+    }
+
+    // Clears the execution state, the stack size and the input/output stay configured
+    pub fn reset(&mut self) {
+        self.stack_pointer = 0;
+        self.program_pointer = 0;
+        self.overflow = false;
+        self.carry = false;
+        self.call_stack.clear();
+        self.return_values.clear();
+        self.frame_pointer = 0;
+        self.exit_code = None;
+        self.locals = [T::ZERO; LOCALS_SIZE];
+        self.instruction_count = 0;
+        self.memory.fill(T::ZERO);
+        if let Some(records) = &mut self.records {
+            records.clear();
+        }
+        self.high_water_mark = 0;
+        self.last_instruction = None;
+        self.rng = self.seed;
+        self.paused_at = None;
+    }
+
+    pub fn stack_slice(&self) -> &[T] {
+        &self.stack[..self.stack_pointer]
+    }
+
+    pub fn top(&self) -> Option<T> {
+        self.stack_slice().last().copied()
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.stack_pointer
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_pointer
+    }
+
+    // With a program length the counter follows the jump rule: anything past `len` is rejected
+    pub fn set_program_counter(&mut self, pc: usize, program_len: Option<usize>) -> Result<(), VmError> {
+        if program_len.is_some_and(|len| pc > len) {
+            return Err(self.error(VmErrorKind::InvalidJump(pc)));
+        }
+        self.program_pointer = pc;
+        Ok(())
+    }
+
+    // Set by the last instruction, the next one sees it through JmpOverflow before it is cleared
+    pub fn overflow(&self) -> bool {
+        self.overflow
+    }
+
+    pub fn set_overflow(&mut self, flag: bool) {
+        self.overflow = flag;
+    }
+
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn instructions_executed(&self) -> u64 {
+        self.instruction_count
+    }
+
+    // Deepest the stack has been since the VM was created or reset
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    pub fn watermark_exceeded(&self) -> bool {
+        self.watermark.is_some_and(|watermark| self.high_water_mark > watermark)
+    }
+
+    // Also set when the instruction failed, it is then the one the error points at
+    pub fn last_instruction_index(&self) -> Option<usize> {
+        self.last_instruction
+    }
+
+    // Needs the program that was run, the VM only keeps the index
+    pub fn last_instruction(&self, program: &Program<T>) -> Option<Instruction<T>> {
+        self.last_instruction.map(|index| program.get(index))
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn records(&self) -> &[StdCallRecord<T>] {
+        self.records.as_deref().unwrap_or_default()
+    }
+
+    pub fn to_snapshot(&self) -> VmSnapshot<T> {
+        VmSnapshot {
+            stack_pointer: self.stack_pointer,
+            program_pointer: self.program_pointer,
+            overflow: self.overflow,
+            stack: self.stack_slice().to_vec(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &VmSnapshot<T>) -> Result<(), VmError> {
+        if snapshot.stack_pointer != snapshot.stack.len() {
+            return Err(self.error(VmErrorKind::InvalidSnapshot));
+        }
+        if snapshot.stack.len() > self.stack.len() {
+            return Err(self.error(VmErrorKind::StackOverflow));
+        }
+        self.stack[..snapshot.stack.len()].copy_from_slice(&snapshot.stack);
+        self.stack_pointer = snapshot.stack_pointer;
+        self.high_water_mark = self.high_water_mark.max(self.stack_pointer);
+        self.program_pointer = snapshot.program_pointer;
+        self.overflow = snapshot.overflow;
+        Ok(())
+    }
+
+    pub fn execute(&mut self, program: &Program<T>, debug: bool) -> Result<(), VmError> {
+        self.execute_steps(program.as_slice(), usize::MAX, debug)
+    }
+
+    // Runs bare instructions as if they were a program, without building one
+    pub fn execute_slice(&mut self, instrs: &[Instruction<T>], debug: bool) -> Result<(), VmError> {
+        self.execute_steps(instrs, usize::MAX, debug)
+    }
+
+    pub fn execute_slice_limited(&mut self, instrs: &[Instruction<T>], max_steps: usize) -> Result<(), VmError> {
+        self.execute_steps(instrs, max_steps, false)
+    }
+
+    pub fn execute_slice_one(&mut self, instrs: &[Instruction<T>]) -> Result<bool, VmError> {
+        self.execute_instruction(instrs).map_err(|kind| self.error(kind))
+    }
+
+    // Runs the programs one after another on the same stack, only the program pointer is reset in between
+    pub fn execute_all(&mut self, programs: &[Program<T>]) -> Result<(), VmError> {
+        for program in programs {
+            self.program_pointer = 0;
+            self.execute(program, false)?;
+        }
+        Ok(())
+    }
+
+    // A panic while running, e.g. from the output writer, becomes an error instead of unwinding into the caller.
+    // The VM state is left wherever the panic happened
+    pub fn execute_caught(&mut self, program: &Program<T>) -> Result<(), VmError> {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.execute(program, false)));
+        result.unwrap_or_else(|payload| {
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(self.error(VmErrorKind::Panic(message)))
+        })
+    }
+
+    // Rejects programs with invalid jumps before running a single instruction
+    pub fn execute_validated(&mut self, program: &Program<T>, debug: bool) -> Result<(), VmError> {
+        program.validate().map_err(|error| self.error(VmErrorKind::Validation(error)))?;
+        self.execute(program, debug)
+    }
+
+    pub fn execute_limited(&mut self, program: &Program<T>, max_steps: usize) -> Result<(), VmError> {
+        self.execute_steps(program.as_slice(), max_steps, false)
+    }
+
+    // Runs the program on a fresh VM and returns the value left on top of the stack
+    pub fn eval(program: &Program<T>) -> Result<Option<T>, VmError> {
+        let mut vm = Self::with_cells();
+        vm.execute_limited(program, EVAL_STEP_LIMIT)?;
+        Ok(vm.top())
+    }
+
+    // The clock is only read every few instructions to keep the loop cheap
+    pub fn execute_timed(&mut self, program: &Program<T>, timeout: Duration) -> Result<(), VmError> {
+        let start = Instant::now();
+        let mut steps: u64 = 0;
+        loop {
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && start.elapsed() > timeout {
+                return Err(self.error(VmErrorKind::TimeoutExceeded));
+            }
+            if !self.execute_one(program)? {
+                return Ok(());
+            }
+            steps += 1;
+        }
+    }
+
+    pub fn step(&mut self, program: &Program<T>) -> Result<StepOutcome, VmError> {
+        let from = self.program_pointer;
+        if !self.execute_one(program)? {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.program_pointer == from + 1 {
+            Ok(StepOutcome::Continued)
+        } else {
+            Ok(StepOutcome::Jumped { to: self.program_pointer })
+        }
+    }
+
+    pub fn execute_until(&mut self, program: &Program<T>, breakpoints: &HashSet<usize>) -> Result<BreakReason, VmError> {
+        // Stops before a breakpointed instruction runs, resuming from it runs it instead of stopping again
+        let mut resumed = self.paused_at.take() == Some(self.program_pointer);
+        loop {
+            if !resumed && breakpoints.contains(&self.program_pointer) {
+                self.paused_at = Some(self.program_pointer);
+                return Ok(BreakReason::Breakpoint(self.program_pointer));
+            }
+            resumed = false;
+            if !self.execute_one(program)? {
+                return Ok(BreakReason::Halted);
+            }
+        }
+    }
+
+    pub fn execute_with_hook(&mut self, program: &Program<T>, mut hook: impl FnMut(usize, &Instruction<T>, &VM<T>)) -> Result<(), VmError> {
+        loop {
+            hook(self.program_pointer, &program.get(self.program_pointer), self);
+            if !self.execute_one(program)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn execute_steps(&mut self, code: &[Instruction<T>], max_steps: usize, debug: bool) -> Result<(), VmError> {
+        for _ in 0..max_steps {
+            if debug {
+                println!("Instruction: {:?}", fetch(code, self.program_pointer));   
+            }
+            if !self.execute_slice_one(code)? {
+                return Ok(());
+            }
+            if debug {
+                println!("{}\n", self);
+            }
+        }
+        Err(self.error(VmErrorKind::StepLimitExceeded))
+    }
+
+    fn stack_push(&mut self, value: T) -> Result<(), VmErrorKind> {
+        if self.stack_pointer >= self.stack.len() {
+            return Err(VmErrorKind::StackOverflow);
+        }
+        self.stack[self.stack_pointer] = value;
+        self.stack_pointer += 1;
+        self.high_water_mark = self.high_water_mark.max(self.stack_pointer);
+        Ok(())
+    }
+
+    fn stack_pop(&mut self) -> Result<T, VmErrorKind> {
+        if self.stack_pointer == 0 {
+            return Err(VmErrorKind::StackUnderflow);
+        }
+        self.stack_pointer -= 1;
+        Ok(self.stack[self.stack_pointer])
+    }
+
+    // Both values or neither, an underflow leaves the single value in place for the error report
+    fn stack_pop_pair(&mut self) -> Result<(T, T), VmErrorKind> {
+        if self.stack_pointer < 2 {
+            return Err(VmErrorKind::StackUnderflow);
+        }
+        let top = self.stack_pop()?;
+        let second = self.stack_pop()?;
+        Ok((top, second))
+    }
+
+    // 16-bit values are stored little-endian: the low byte is pushed first, the high byte ends on top
+    fn stack_push_u16(&mut self, value: u16) -> Result<(), VmErrorKind> {
+        let [low, high] = value.to_le_bytes();
+        self.stack_push(T::from_u8(low))?;
+        self.stack_push(T::from_u8(high))
+    }
+
+    fn stack_pop_u16(&mut self) -> Result<u16, VmErrorKind> {
+        let (high, low) = self.stack_pop_pair()?;
+        Ok(u16::from_le_bytes([low.as_u8(), high.as_u8()]))
+    }
+
+    // Reads the value `depth` slots below the top of the stack without popping it
+    fn stack_peek(&self, depth: usize) -> Result<T, VmErrorKind> {
+        if depth >= self.stack_pointer {
+            return Err(VmErrorKind::StackUnderflow);
+        }
+        Ok(self.stack[self.stack_pointer - 1 - depth])
+    }
+
+    fn arg_index(&self, slot: u8) -> Result<usize, VmErrorKind> {
+        let index = self.frame_pointer.checked_sub(slot as usize + 1).ok_or(VmErrorKind::StackUnderflow)?;
+        if index >= self.stack_pointer {
+            return Err(VmErrorKind::StackUnderflow);
+        }
+        Ok(index)
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, VmErrorKind> {
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(error) => Err(VmErrorKind::Io(error.kind())),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String, VmErrorKind> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte()? {
+                Some(b'\n') => break,
+                Some(byte) => line.push(byte),
+                None if line.is_empty() => return Err(VmErrorKind::EndOfInput),
+                None => break,
+            }
+        }
+        String::from_utf8(line).map_err(|_| VmErrorKind::InvalidInput)
+    }
+
+    fn next_random(&mut self) -> u8 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        // xorshift64*: scrambling the state keeps small seeds from starting with zeros
+        (self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    fn format_number(&self, value: T) -> String {
+        match self.print_radix {
+            2 => format!("{:b}", value),
+            8 => format!("{:o}", value),
+            16 => format!("{:x}", value),
+            _ => value.to_string(),
+        }
+    }
+
+    fn print(&mut self, value: impl Display) -> Result<(), VmErrorKind> {
+        if let Some(record) = self.records.as_mut().and_then(|records| records.last_mut()) {
+            record.output.push_str(&value.to_string());
+        }
+        write!(self.out, "{}", value).map_err(|error| VmErrorKind::Io(error.kind()))
+    }
+
+    fn jump(&mut self, code: &[Instruction<T>], location: usize) -> Result<(), VmErrorKind> {
+        // Jumping right past the last instruction is allowed, it halts the program
+        if location > code.len() {
+            return Err(VmErrorKind::InvalidJump(location));
+        }
+        self.program_pointer = location;
+        Ok(())
+    }
+
+    fn jump_relative(&mut self, code: &[Instruction<T>], offset: i16) -> Result<(), VmErrorKind> {
+        match relative_target(self.program_pointer, offset) {
+            Some(location) if location <= code.len() => {
+                self.program_pointer = location;
+                Ok(())
+            },
+            _ => Err(VmErrorKind::InvalidRelativeJump(offset)),
+        }
+    }
+
+    // Compares (top, second) without consuming them
+    fn compare(&mut self, condition: impl FnOnce(T, T) -> bool) -> Result<bool, VmErrorKind> {
+        let (lhs, rhs) = self.stack_pop_pair()?;
+        // Push the values back once compared
+        self.stack_push(rhs)?;
+        self.stack_push(lhs)?;
+        Ok(condition(lhs, rhs))
+    }
+
+    // Jumps when the comparison of (top, second) holds
+    fn jump_if(&mut self, code: &[Instruction<T>], location: usize, condition: impl FnOnce(T, T) -> bool) -> Result<bool, VmErrorKind> {
+        if self.compare(condition)? {
+            self.jump(code, location)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    pub fn execute_one(&mut self, program: &Program<T>) -> Result<bool, VmError> {
+        self.execute_slice_one(program.as_slice())
+    }
+
+    fn error(&self, kind: VmErrorKind) -> VmError {
+        VmError { kind, at: self.program_pointer }
+    }
+
+    fn execute_instruction(&mut self, code: &[Instruction<T>]) -> Result<bool, VmErrorKind> {
+        let instruction = fetch(code, self.program_pointer);
+        self.last_instruction = Some(self.program_pointer);
+        // The instruction does not run at all when the fuel left cannot pay for it
+        if let Some(fuel) = &mut self.fuel {
+            *fuel = fuel.checked_sub(instruction.cost()).ok_or(VmErrorKind::OutOfFuel)?;
+        }
+        self.instruction_count += 1;
+        // The flag only reflects the most recent instruction, arithmetic ones set it again
+        let overflow = std::mem::take(&mut self.overflow);
+
+        match instruction {
+            Instruction::Push(value) => self.stack_push(value)?,
+            Instruction::PushStr(value) => {
+                // The UTF-8 bytes go on reversed so the first one ends on top
+                self.stack_push(T::ZERO)?;
+                for byte in value.bytes().rev() {
+                    self.stack_push(T::from_u8(byte))?;
+                }
+            },
+            // Same byte order as PushStr but with the length on top instead of a terminator below
+            Instruction::PushCountedStr(value) => {
+                let length = u8::try_from(value.len()).map_err(|_| VmErrorKind::StringTooLong(value.len()))?;
+                if self.stack.len() - self.stack_pointer <= value.len() {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                for byte in value.bytes().rev() {
+                    self.stack_push(T::from_u8(byte))?;
+                }
+                self.stack_push(T::from_u8(length))?;
+            },
+            Instruction::PushChar(chr) => {
+                let value = u8::try_from(chr).map_err(|_| VmErrorKind::InvalidChar(chr))?;
+                self.stack_push(T::from_u8(value))?;
+            },
+            Instruction::PushAll(values) => {
+                // Check up front so an overflow leaves the stack untouched
+                if self.stack.len() - self.stack_pointer < values.len() {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                for value in values {
+                    self.stack_push(value)?;
+                }
+            },
+            Instruction::Pop => _ = self.stack_pop()?,
+            Instruction::DropN(count) => {
+                let count = count as usize;
+                if count > self.stack_pointer {
+                    return Err(VmErrorKind::StackUnderflow);
+                }
+                self.stack_pointer -= count;
+            },
+            Instruction::Dup => {
+                let value = self.stack_peek(0)?;
+                self.stack_push(value)?;
+            },
+            // Copies the top `count` values as a block, keeping their order
+            Instruction::DupN(count) => {
+                let count = count as usize;
+                if count > self.stack_pointer {
+                    return Err(VmErrorKind::StackUnderflow);
+                }
+                if count > self.stack.len() - self.stack_pointer {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                self.stack.copy_within(self.stack_pointer - count..self.stack_pointer, self.stack_pointer);
+                self.stack_pointer += count;
+                self.high_water_mark = self.high_water_mark.max(self.stack_pointer);
+            },
+            Instruction::Swap => {
+                let (top, below) = self.stack_pop_pair()?;
+                self.stack_push(top)?;
+                self.stack_push(below)?;
+            },
+            Instruction::Over => {
+                let value = self.stack_peek(1)?;
+                self.stack_push(value)?;
+            },
+            // Copies the value `depth` slots below the top, Pick(0) behaves like Dup and Pick(1) like Over
+            Instruction::Pick(depth) => {
+                let value = self.stack_peek(depth as usize)?;
+                self.stack_push(value)?;
+            },
+            // Moves the deepest of the top `count` values to the top
+            Instruction::Rot(count) => {
+                let count = count as usize;
+                if count > self.stack_pointer {
+                    return Err(VmErrorKind::StackUnderflow);
+                }
+                if count > 0 {
+                    self.stack[self.stack_pointer - count..self.stack_pointer].rotate_left(1);
+                }
+            },
+            Instruction::Depth => {
+                let depth = T::from_usize(self.stack_pointer);
+                self.stack_push(depth)?;
+            },
+            Instruction::ClearStack => self.stack_pointer = 0,
+            Instruction::Nop => {},
+            Instruction::Store(slot) => {
+                let value = self.stack_pop()?;
+                *self.locals.get_mut(slot as usize).ok_or(VmErrorKind::InvalidLocal(slot))? = value;
+            },
+            Instruction::Load(slot) => {
+                let value = *self.locals.get(slot as usize).ok_or(VmErrorKind::InvalidLocal(slot))?;
+                self.stack_push(value)?;
+            },
+            // Same as Push followed by Store, without going through the stack
+            Instruction::SetLocal(slot, value) => {
+                *self.locals.get_mut(slot as usize).ok_or(VmErrorKind::InvalidLocal(slot))? = value;
+            },
+            // Arguments sit right below the frame pointer, argument 0 is the last value pushed before the call
+            Instruction::StoreArg(slot) => {
+                let value = self.stack_pop()?;
+                let index = self.arg_index(slot)?;
+                self.stack[index] = value;
+            },
+            Instruction::LoadArg(slot) => {
+                let value = self.stack[self.arg_index(slot)?];
+                self.stack_push(value)?;
+            },
+            Instruction::MemStore => {
+                let (address, value) = self.stack_pop_pair()?;
+                let address = address.to_usize();
+                *self.memory.get_mut(address).ok_or(VmErrorKind::OutOfBoundsMemory(address))? = value;
+            },
+            Instruction::MemLoad => {
+                let address = self.stack_pop()?.to_usize();
+                let value = *self.memory.get(address).ok_or(VmErrorKind::OutOfBoundsMemory(address))?;
+                self.stack_push(value)?;
+            },
+            Instruction::Add => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.overflowing_add(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+                self.carry = overflow;
+            },
+            Instruction::Sub => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.overflowing_sub(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+                self.carry = overflow;
+            },
+            Instruction::Mul => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.overflowing_mul(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::Div => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                if rhs == T::ZERO {
+                    return Err(VmErrorKind::DivideByZero);
+                }
+                self.stack_push(lhs / rhs)?;
+            },
+            Instruction::Mod => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                if rhs == T::ZERO {
+                    return Err(VmErrorKind::DivideByZero);
+                }
+                self.stack_push(lhs % rhs)?;
+            },
+            Instruction::Neg => {
+                let value = self.stack_pop()?;
+                self.stack_push(value.wrapping_neg())?;
+            },
+            Instruction::Inc => {
+                let value = self.stack_pop()?;
+                let (value, overflow) = value.overflowing_add(T::ONE);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::Dec => {
+                let value = self.stack_pop()?;
+                let (value, overflow) = value.overflowing_sub(T::ONE);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::AddCarry => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, first) = lhs.overflowing_add(rhs);
+                let (value, second) = value.overflowing_add(T::from_bool(self.carry));
+                self.stack_push(value)?;
+                self.overflow = first || second;
+                self.carry = first || second;
+            },
+            Instruction::SubBorrow => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, first) = lhs.overflowing_sub(rhs);
+                let (value, second) = value.overflowing_sub(T::from_bool(self.carry));
+                self.stack_push(value)?;
+                self.overflow = first || second;
+                self.carry = first || second;
+            },
+            // Signed variants reinterpret the cells as two's complement and store back the bit pattern
+            Instruction::AddI => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.signed_overflowing_add(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::SubI => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.signed_overflowing_sub(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::MulI => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let (value, overflow) = lhs.signed_overflowing_mul(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            // Truncates toward zero like Rust's signed division, so -7 / 2 == -3
+            Instruction::DivI => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                if rhs == T::ZERO {
+                    return Err(VmErrorKind::DivideByZero);
+                }
+                let (value, overflow) = lhs.signed_overflowing_div(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            // The remainder takes the sign of the dividend, matching the truncating DivI
+            Instruction::ModI => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                if rhs == T::ZERO {
+                    return Err(VmErrorKind::DivideByZero);
+                }
+                let (value, overflow) = lhs.signed_overflowing_rem(rhs);
+                self.stack_push(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::PushI16(value) => self.stack_push_u16(value)?,
+            Instruction::AddI16 => {
+                if self.stack_pointer < 4 {
+                    return Err(VmErrorKind::StackUnderflow);
+                }
+                let lhs = self.stack_pop_u16()?;
+                let rhs = self.stack_pop_u16()?;
+                let (value, overflow) = lhs.overflowing_add(rhs);
+                self.stack_push_u16(value)?;
+                self.overflow = overflow;
+            },
+            Instruction::SubI16 => {
+                if self.stack_pointer < 4 {
+                    return Err(VmErrorKind::StackUnderflow);
+                }
+                let lhs = self.stack_pop_u16()?;
+                let rhs = self.stack_pop_u16()?;
+                let (value, overflow) = lhs.overflowing_sub(rhs);
+                self.stack_push_u16(value)?;
+                self.overflow = overflow;
+            },
+            // Comparisons push 1 when the condition holds for (top, second), 0 otherwise
+            Instruction::Lt => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(T::from_bool(lhs < rhs))?;
+            },
+            Instruction::Gt => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(T::from_bool(lhs > rhs))?;
+            },
+            Instruction::Eq => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(T::from_bool(lhs == rhs))?;
+            },
+            // Three-way comparison of top against second: 255 (-1 as signed) when top is less, 0 when equal, 1 when greater
+            Instruction::Cmp => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                let value = match lhs.cmp(&rhs) {
+                    std::cmp::Ordering::Less => T::ONE.wrapping_neg(),
+                    std::cmp::Ordering::Equal => T::ZERO,
+                    std::cmp::Ordering::Greater => T::ONE,
+                };
+                self.stack_push(value)?;
+            },
+            Instruction::Min => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(lhs.min(rhs))?;
+            },
+            Instruction::Max => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(lhs.max(rhs))?;
+            },
+            Instruction::ToBool => {
+                let value = self.stack_pop()?;
+                self.stack_push(T::from_bool(value != T::ZERO))?;
+            },
+            // Only ASCII letters change, anything else is left as it is
+            Instruction::ToUpper => {
+                let value = self.stack_pop()?;
+                self.stack_push(map_ascii(value, u8::to_ascii_uppercase))?;
+            },
+            Instruction::ToLower => {
+                let value = self.stack_pop()?;
+                self.stack_push(map_ascii(value, u8::to_ascii_lowercase))?;
+            },
+            Instruction::And => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(lhs & rhs)?;
+            },
+            Instruction::Or => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(lhs | rhs)?;
+            },
+            Instruction::Xor => {
+                let (lhs, rhs) = self.stack_pop_pair()?;
+                self.stack_push(lhs ^ rhs)?;
+            },
+            Instruction::Not => {
+                let value = self.stack_pop()?;
+                self.stack_push(!value)?;
+            },
+            // Shifts pop the amount first, shifting by the cell width or more clears the value
+            Instruction::Shl => {
+                let (amount, value) = self.stack_pop_pair()?;
+                self.stack_push(value.checked_shl(amount.to_usize().try_into().unwrap_or(u32::MAX)).unwrap_or(T::ZERO))?;
+            },
+            Instruction::Shr => {
+                let (amount, value) = self.stack_pop_pair()?;
+                self.stack_push(value.checked_shr(amount.to_usize().try_into().unwrap_or(u32::MAX)).unwrap_or(T::ZERO))?;
+            },
+            Instruction::JmpEq(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs == rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpNeq(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs != rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpLt(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs < rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpGt(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs > rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpLe(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs <= rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpGe(location) => {
+                if self.jump_if(code, location, |lhs, rhs| lhs >= rhs)? {
+                    return Ok(true);
+                }
+            },
+            Instruction::Jmp(location) => {
+                self.jump(code, location)?;
+                return Ok(true);
+            },
+            Instruction::JmpOverflow(location) => {
+                if overflow {
+                    self.jump(code, location)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpIf(location) => {
+                if self.stack_pop()? != T::ZERO {
+                    self.jump(code, location)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpIfNot(location) => {
+                if self.stack_pop()? == T::ZERO {
+                    self.jump(code, location)?;
+                    return Ok(true);
+                }
+            },
+            // Relative offsets count from the jump itself, so the code can be moved around
+            Instruction::JmpRel(offset) => {
+                self.jump_relative(code, offset)?;
+                return Ok(true);
+            },
+            Instruction::JmpEqRel(offset) => {
+                if self.compare(|lhs, rhs| lhs == rhs)? {
+                    self.jump_relative(code, offset)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpNeqRel(offset) => {
+                if self.compare(|lhs, rhs| lhs != rhs)? {
+                    self.jump_relative(code, offset)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpIfRel(offset) => {
+                if self.stack_pop()? != T::ZERO {
+                    self.jump_relative(code, offset)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::JmpIfNotRel(offset) => {
+                if self.stack_pop()? == T::ZERO {
+                    self.jump_relative(code, offset)?;
+                    return Ok(true);
+                }
+            },
+            Instruction::Call(location) => {
+                if self.call_stack.len() + self.return_values.len() >= self.max_call_depth {
+                    return Err(VmErrorKind::CallStackOverflow);
+                }
+                let return_address = self.program_pointer + 1;
+                self.jump(code, location)?;
+                // The callee's frame starts right above the arguments pushed by the caller
+                self.call_stack.push((return_address, self.frame_pointer));
+                self.frame_pointer = self.stack_pointer;
+                return Ok(true);
+            },
+            Instruction::Ret => {
+                let (return_address, frame_pointer) = self.call_stack.pop().ok_or(VmErrorKind::CallStackUnderflow)?;
+                self.program_pointer = return_address;
+                self.frame_pointer = frame_pointer;
+                return Ok(true);
+            },
+            // Like Forth's >R and R>, the return stack doubles as a scratch area during computation
+            Instruction::ToR => {
+                if self.call_stack.len() + self.return_values.len() >= self.max_call_depth {
+                    return Err(VmErrorKind::CallStackOverflow);
+                }
+                let value = self.stack_pop()?;
+                self.return_values.push(value);
+            },
+            Instruction::FromR => {
+                let value = *self.return_values.last().ok_or(VmErrorKind::CallStackUnderflow)?;
+                self.stack_push(value)?;
+                self.return_values.pop();
+            },
+            Instruction::StdCall(id) => {
+                let func = StdFunc::from_id(id).ok_or(VmErrorKind::InvalidStdCall(id))?;
+                let depth = self.stack_pointer;
+                if let Some(records) = &mut self.records {
+                    records.push(StdCallRecord { func, consumed: Vec::new(), output: String::new() });
+                }
+                match func {
+                    StdFunc::PrintU8 => {
+                        let value = self.stack_pop()?;
+                        self.print(self.format_number(value))?;
+                    },
+                    StdFunc::PrintChar => {
+                        let value = self.stack_pop()?.as_u8() as char;
+                        self.print(value)?;
+                    },
+                    StdFunc::PrintString => {
+                        // Find the terminator first so a malformed string leaves the stack untouched
+                        let length = self.stack[..self.stack_pointer].iter().rev().position(|value| *value == T::ZERO)
+                            .ok_or(VmErrorKind::MissingStringTerminator)?;
+                        let bytes: Vec<u8> = self.stack[self.stack_pointer - length..self.stack_pointer].iter().rev()
+                            .map(|value| value.as_u8())
+                            .collect();
+                        self.print(String::from_utf8_lossy(&bytes))?;
+                        self.stack_pointer -= length + 1;
+                    },
+                    StdFunc::Clone => {
+                        let value = self.stack_peek(0)?;
+                        self.stack_push(value)?;
+                    },
+                    StdFunc::PrintHex => {
+                        let value = self.stack_pop()?;
+                        self.print(format_args!("{:02x}", value))?;
+                    },
+                    StdFunc::PrintMemString => {
+                        let start = self.stack_pop()?.to_usize();
+                        if start >= self.memory.len() {
+                            return Err(VmErrorKind::OutOfBoundsMemory(start));
+                        }
+                        let length = self.memory[start..].iter().position(|value| *value == T::ZERO)
+                            .ok_or(VmErrorKind::MissingStringTerminator)?;
+                        let bytes: Vec<u8> = self.memory[start..start + length].iter()
+                            .map(|value| value.as_u8())
+                            .collect();
+                        self.print(String::from_utf8_lossy(&bytes))?;
+                    },
+                    // Sums the whole stack without consuming it
+                    StdFunc::Checksum => {
+                        let sum = self.stack_slice().iter().fold(0u8, |sum, value| sum.wrapping_add(value.as_u8()));
+                        self.stack_push(T::from_u8(sum))?;
+                    },
+                    StdFunc::PrintU8Ln => {
+                        let value = self.stack_pop()?;
+                        self.print(format_args!("{}\n", self.format_number(value)))?;
+                    },
+                    // Same view as the VM's Display, the stack is left untouched
+                    StdFunc::PrintStack => {
+                        let view = StackView(self.stack_slice()).to_string();
+                        self.print(format_args!("{}\n", view))?;
+                    },
+                    // Only the low byte of the count, this std call included, so it wraps every 256 instructions
+                    StdFunc::Cycles => self.stack_push(T::from_u8(self.instruction_count as u8))?,
+                    StdFunc::Rand => {
+                        let value = self.next_random();
+                        self.stack_push(T::from_u8(value))?;
+                    },
+                    StdFunc::PrintCountedString => {
+                        let length = self.stack_peek(0)?.to_usize();
+                        if length >= self.stack_pointer {
+                            return Err(VmErrorKind::StackUnderflow);
+                        }
+                        let top = self.stack_pointer - 1;
+                        let bytes: Vec<u8> = self.stack[top - length..top].iter().rev()
+                            .map(|value| value.as_u8())
+                            .collect();
+                        self.print(String::from_utf8_lossy(&bytes))?;
+                        self.stack_pointer -= length + 1;
+                    },
+                    StdFunc::ReadU8 => {
+                        let line = self.read_line()?;
+                        let value = line.trim().parse().map_err(|_| VmErrorKind::InvalidInput)?;
+                        self.stack_push(value)?;
+                    },
+                    StdFunc::ReadChar => {
+                        let value = self.read_byte()?.ok_or(VmErrorKind::EndOfInput)?;
+                        self.stack_push(T::from_u8(value))?;
+                    },
+                }
+                if let Some(record) = self.records.as_mut().and_then(|records| records.last_mut()) {
+                    record.consumed = self.stack[self.stack_pointer.min(depth)..depth].iter().rev().copied().collect();
+                }
+            },
+            Instruction::Assert(expected) => {
+                let value = self.stack_pop()?;
+                if value != T::from_u8(expected) {
+                    return Err(VmErrorKind::AssertionFailed { expected, got: value.to_u64() });
+                }
+            },
+            // Stopping keeps the flag of the last instruction so host code can still read it
+            Instruction::Halt(code) => {
+                self.overflow = overflow;
+                self.exit_code = Some(code);
+                return Ok(false);
+            },
+            Instruction::Interupt => {
+                self.overflow = overflow;
+                self.exit_code = Some(0);
+                return Ok(false);
+            },
+        }
+        self.program_pointer += 1;
+
+        Ok(true)
+    }
+
+}
+
+struct StackView<'a, T>(&'a [T]);
+
+impl<T: Cell> Display for StackView<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Stack [{}]:", self.0.len())?;
+        for (index, value) in self.0.iter().enumerate() {
+            write!(f, "{:02x} ", value)?;
+            if index % 32 == 31 {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Cell> Display for VM<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Program Pointer: {}", self.program_pointer)?;
+        write!(f, "{}", StackView(self.stack_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn run(instructions: Vec<Instruction>) -> VM {
+        let mut vm = VM::with_output(Box::new(std::io::sink()));
+        vm.execute(&Program::from(instructions), false).unwrap();
+        vm
+    }
+
+    fn run_err(instructions: Vec<Instruction>) -> VmError {
+        let mut vm = VM::with_output(Box::new(std::io::sink()));
+        vm.execute(&Program::from(instructions), false).unwrap_err()
+    }
+
+    #[test]
+    fn execute_returns_ok_when_the_program_ends() {
+        assert_eq!(run(vec![Instruction::Push(1), Instruction::Push(2)]).stack_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn stack_overflow_is_an_error() {
+        let error = run_err(vec![Instruction::Push(1); STACK_SIZE + 1]);
+        assert_eq!(error, VmError { kind: VmErrorKind::StackOverflow, at: STACK_SIZE });
+    }
+
+    #[test]
+    fn stack_underflow_is_an_error() {
+        let error = run_err(vec![Instruction::Push(1), Instruction::Pop, Instruction::Pop]);
+        assert_eq!(error, VmError { kind: VmErrorKind::StackUnderflow, at: 2 });
+        assert_eq!(error.to_string(), "error at instruction 2: stack underflow");
+    }
+
+    #[test]
+    fn execute_one_reports_whether_to_continue() {
+        let program = Program::from(vec![Instruction::Push(1)]);
+        let mut vm = VM::new();
+        assert_eq!(vm.execute_one(&program), Ok(true));
+        assert_eq!(vm.execute_one(&program), Ok(false));
+        assert_eq!(vm.stack_slice(), &[1]);
+    }
+
+    #[test]
+    fn dup_copies_the_top() {
+        let vm = run(vec![Instruction::Push(42), Instruction::Dup]);
+        assert_eq!(vm.stack_slice(), &[42, 42]);
+        assert_eq!(vm.stack_pointer(), 2);
+    }
+
+    #[test]
+    fn dup_on_empty_stack_underflows() {
+        assert_eq!(run_err(vec![Instruction::Dup]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two() {
+        let vm = run(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Swap]);
+        assert_eq!(vm.stack_slice(), &[2, 1]);
+    }
+
+    #[test]
+    fn over_copies_the_second() {
+        let vm = run(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Over]);
+        assert_eq!(vm.stack_slice(), &[1, 2, 1]);
+    }
+
+    #[test]
+    fn swap_and_over_need_two_values() {
+        assert_eq!(run_err(vec![Instruction::Push(1), Instruction::Swap]).kind, VmErrorKind::StackUnderflow);
+        assert_eq!(run_err(vec![Instruction::Push(1), Instruction::Over]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn unknown_std_call_is_an_error() {
+        assert_eq!(run_err(vec![Instruction::StdCall(99)]).kind, VmErrorKind::InvalidStdCall(99));
+        assert_eq!(StdFunc::from_id(99), None);
+    }
+
+    #[test]
+    fn std_func_ids_map_back() {
+        for func in [StdFunc::PrintU8, StdFunc::PrintChar, StdFunc::PrintString, StdFunc::Clone] {
+            assert_eq!(StdFunc::from_id(func as usize), Some(func));
+        }
+    }
+
+    #[test]
+    fn mod_pushes_the_remainder() {
+        let vm = run(vec![Instruction::Push(5), Instruction::Push(17), Instruction::Mod]);
+        assert_eq!(vm.stack_slice(), &[2]);
+    }
+
+    #[test]
+    fn mod_by_zero_is_an_error() {
+        let error = run_err(vec![Instruction::Push(0), Instruction::Push(17), Instruction::Mod]);
+        assert_eq!(error.kind, VmErrorKind::DivideByZero);
+    }
+
+    #[test]
+    fn mul_overflow_sets_the_flag() {
+        let vm = run(vec![Instruction::Push(200), Instruction::Push(200), Instruction::Mul]);
+        assert_eq!(vm.stack_slice(), &[200u8.wrapping_mul(200)]);
+        assert!(vm.overflow());
+        assert!(!run(vec![Instruction::Push(2), Instruction::Push(3), Instruction::Mul]).overflow());
+    }
+
+    #[test]
+    fn div_by_zero_is_an_error() {
+        let error = run_err(vec![Instruction::Push(0), Instruction::Push(5), Instruction::Div]);
+        assert_eq!(error, VmError { kind: VmErrorKind::DivideByZero, at: 2 });
+    }
+
+    #[test]
+    fn signed_sub_goes_negative() {
+        let vm = run(vec![Instruction::Push(5), Instruction::Push(3), Instruction::SubI]);
+        assert_eq!(vm.stack_slice(), &[-2i8 as u8]);
+    }
+
+    #[test]
+    fn signed_div_keeps_the_sign() {
+        let vm = run(vec![Instruction::Push(2), Instruction::Push(-6i8 as u8), Instruction::DivI]);
+        assert_eq!(vm.stack_slice(), &[-3i8 as u8]);
+    }
+
+    #[test]
+    fn signed_add_and_mul() {
+        let vm = run(vec![Instruction::Push(-4i8 as u8), Instruction::Push(1), Instruction::AddI]);
+        assert_eq!(vm.stack_slice(), &[-3i8 as u8]);
+        let vm = run(vec![Instruction::Push(-4i8 as u8), Instruction::Push(3), Instruction::MulI]);
+        assert_eq!(vm.stack_slice(), &[-12i8 as u8]);
+    }
+
+    #[test]
+    fn call_returns_to_the_caller() {
+        let vm = run(vec![
+            Instruction::Push(2),
+            Instruction::Push(3),
+            Instruction::Call(4),
+            Instruction::Jmp(6),
+            Instruction::Add,
+            Instruction::Ret,
+        ]);
+        assert_eq!(vm.stack_slice(), &[5]);
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let mut vm = VM::new();
+        vm.set_max_call_depth(2);
+        let error = vm.execute(&Program::from(vec![Instruction::Call(0)]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::CallStackOverflow);
+    }
+
+    #[test]
+    fn ret_without_call_underflows() {
+        assert_eq!(run_err(vec![Instruction::Ret]).kind, VmErrorKind::CallStackUnderflow);
+    }
+
+    #[test]
+    fn jmp_overflow_follows_the_last_add() {
+        let vm = run(vec![
+            Instruction::Push(255),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::JmpOverflow(5),
+            Instruction::Push(9),
+        ]);
+        assert_eq!(vm.stack_slice(), &[0]);
+        let vm = run(vec![Instruction::Push(255), Instruction::Push(1), Instruction::Add]);
+        assert!(vm.overflow());
+    }
+
+    #[test]
+    fn other_instructions_clear_the_overflow() {
+        let vm = run(vec![
+            Instruction::Push(255),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::Push(7),
+            Instruction::JmpOverflow(6),
+            Instruction::Push(9),
+        ]);
+        assert_eq!(vm.stack_slice(), &[0, 7, 9]);
+    }
+
+    #[test]
+    fn comparisons_push_a_boolean() {
+        assert_eq!(run(vec![Instruction::Push(5), Instruction::Push(3), Instruction::Lt]).stack_slice(), &[1]);
+        assert_eq!(run(vec![Instruction::Push(3), Instruction::Push(5), Instruction::Gt]).stack_slice(), &[1]);
+        assert_eq!(run(vec![Instruction::Push(4), Instruction::Push(4), Instruction::Eq]).stack_slice(), &[1]);
+        assert_eq!(run(vec![Instruction::Push(3), Instruction::Push(5), Instruction::Lt]).stack_slice(), &[0]);
+        assert_eq!(run(vec![Instruction::Push(4), Instruction::Push(5), Instruction::Eq]).stack_slice(), &[0]);
+    }
+
+    #[test]
+    fn bitwise_and_or_xor() {
+        assert_eq!(run(vec![Instruction::Push(0x0f), Instruction::Push(0xf0), Instruction::And]).stack_slice(), &[0x00]);
+        assert_eq!(run(vec![Instruction::Push(0x0f), Instruction::Push(0xf0), Instruction::Or]).stack_slice(), &[0xff]);
+        assert_eq!(run(vec![Instruction::Push(0xff), Instruction::Push(0xf0), Instruction::Xor]).stack_slice(), &[0x0f]);
+    }
+
+    #[test]
+    fn not_complements() {
+        assert_eq!(run(vec![Instruction::Push(0x00), Instruction::Not]).stack_slice(), &[0xff]);
+    }
+
+    #[test]
+    fn shifts_pop_the_amount_first() {
+        assert_eq!(run(vec![Instruction::Push(1), Instruction::Push(3), Instruction::Shl]).stack_slice(), &[8]);
+        assert_eq!(run(vec![Instruction::Push(0x80), Instruction::Push(7), Instruction::Shr]).stack_slice(), &[1]);
+        assert_eq!(run(vec![Instruction::Push(1), Instruction::Push(8), Instruction::Shl]).stack_slice(), &[0]);
+        assert_eq!(run(vec![Instruction::Push(0xff), Instruction::Push(200), Instruction::Shr]).stack_slice(), &[0]);
+    }
+
+    #[test]
+    fn step_limit_stops_infinite_loops() {
+        let mut vm = VM::new();
+        let error = vm.execute_limited(&Program::from(vec![Instruction::Jmp(0)]), 100).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::StepLimitExceeded);
+        assert_eq!(vm.instructions_executed(), 100);
+    }
+
+    #[test]
+    fn step_limit_allows_finishing_programs() {
+        let mut vm = VM::new();
+        vm.execute_limited(&Program::from(vec![Instruction::Push(1), Instruction::Dup]), 3).unwrap();
+        assert_eq!(vm.stack_slice(), &[1, 1]);
+    }
+
+    #[test]
+    fn push_str_takes_runtime_strings() {
+        let name = format!("{}{}", "h", "i");
+        let vm = run(vec![Instruction::PushStr(name)]);
+        assert_eq!(vm.stack_slice(), &[0, b'i', b'h']);
+    }
+
+    fn run_with_input(input: &'static [u8], instructions: Vec<Instruction>) -> Result<Vec<u8>, VmErrorKind> {
+        let mut vm = VM::with_input(Box::new(input));
+        vm.execute(&Program::from(instructions), false).map_err(|error| error.kind)?;
+        Ok(vm.stack_slice().to_vec())
+    }
+
+    #[test]
+    fn read_u8_parses_a_line() {
+        assert_eq!(run_with_input(b"42\n7\n", vec![Instruction::StdCall(4), Instruction::StdCall(4)]), Ok(vec![42, 7]));
+    }
+
+    #[test]
+    fn read_u8_rejects_bad_input() {
+        assert_eq!(run_with_input(b"300\n", vec![Instruction::StdCall(4)]), Err(VmErrorKind::InvalidInput));
+        assert_eq!(run_with_input(b"", vec![Instruction::StdCall(4)]), Err(VmErrorKind::EndOfInput));
+    }
+
+    #[test]
+    fn read_char_pushes_a_byte() {
+        assert_eq!(run_with_input(b"ab", vec![Instruction::StdCall(5), Instruction::StdCall(5)]), Ok(b"ab".to_vec()));
+    }
+
+    // The VM owns its writer, the test keeps a second handle to the buffer
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output_of(instructions: Vec<Instruction>) -> String {
+        let output = SharedOutput::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        vm.execute(&Program::from(instructions), false).unwrap();
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn hello_world_goes_to_the_output() {
+        let output = output_of(vec![Instruction::PushStr("Hello, World!\n".to_string()), Instruction::StdCall(2)]);
+        assert_eq!(output, "Hello, World!\n");
+    }
+
+    #[test]
+    fn print_u8_and_print_char_use_the_output() {
+        let output = output_of(vec![Instruction::Push(42), Instruction::StdCall(0), Instruction::Push(b'!'), Instruction::StdCall(1)]);
+        assert_eq!(output, "42!");
+    }
+
+    #[test]
+    fn print_string_stops_at_the_terminator() {
+        let output = SharedOutput::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        let program = Program::from(vec![Instruction::Push(7), Instruction::PushStr("hi".to_string()), Instruction::StdCall(2)]);
+        vm.execute(&program, false).unwrap();
+        assert_eq!(&*output.0.borrow(), b"hi");
+        assert_eq!(vm.stack_slice(), &[7]);
+    }
+
+    #[test]
+    fn print_string_without_terminator_leaves_the_stack() {
+        let mut vm = VM::with_output(Box::new(std::io::sink()));
+        let program = Program::from(vec![Instruction::Push(b'a'), Instruction::Push(b'b'), Instruction::StdCall(2)]);
+        let error = vm.execute(&program, false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::MissingStringTerminator);
+        assert_eq!(vm.stack_slice(), b"ab");
+    }
+
+    #[test]
+    fn stack_accessors() {
+        let vm = run(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Push(3), Instruction::Pop]);
+        assert_eq!(vm.stack_slice(), &[1, 2]);
+        assert_eq!(vm.top(), Some(2));
+        assert_eq!(vm.stack_pointer(), 2);
+        let vm = VM::new();
+        assert_eq!(vm.stack_slice(), &[] as &[u8]);
+        assert_eq!(vm.top(), None);
+        assert_eq!(vm.stack_pointer(), 0);
+    }
+
+    // Counts from 0 up to 10, the loop body is at 3..=5
+    fn counting_loop() -> Program {
+        Program::from(vec![
+            Instruction::Push(10),
+            Instruction::Push(0),
+            Instruction::JmpEq(6),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::Jmp(2),
+        ])
+    }
+
+    #[test]
+    fn step_reports_each_move() {
+        let program = counting_loop();
+        let mut vm = VM::new();
+        let mut outcomes = Vec::new();
+        for _ in 0..7 {
+            outcomes.push(vm.step(&program).unwrap());
+        }
+        assert_eq!(outcomes, [
+            StepOutcome::Continued,
+            StepOutcome::Continued,
+            StepOutcome::Continued,
+            StepOutcome::Continued,
+            StepOutcome::Continued,
+            StepOutcome::Jumped { to: 2 },
+            StepOutcome::Continued,
+        ]);
+        assert_eq!(vm.program_counter(), 3);
+    }
+
+    #[test]
+    fn step_ends_with_halted() {
+        let program = counting_loop();
+        let mut vm = VM::new();
+        let mut last = StepOutcome::Continued;
+        while last != StepOutcome::Halted {
+            if last == (StepOutcome::Jumped { to: 6 }) {
+                assert_eq!(vm.stack_slice(), &[10, 10]);
+            }
+            last = vm.step(&program).unwrap();
+        }
+        assert_eq!(vm.program_counter(), 6);
+    }
+
+    #[test]
+    fn execute_until_stops_inside_the_loop() {
+        let program = counting_loop();
+        let breakpoints = HashSet::from([4]);
+        let mut vm = VM::new();
+        assert_eq!(vm.execute_until(&program, &breakpoints), Ok(BreakReason::Breakpoint(4)));
+        assert_eq!(vm.stack_slice(), &[10, 0, 1]);
+        // Resuming runs the breakpointed instruction and stops on the next pass
+        assert_eq!(vm.execute_until(&program, &breakpoints), Ok(BreakReason::Breakpoint(4)));
+        assert_eq!(vm.stack_slice(), &[10, 1, 1]);
+        assert_eq!(vm.execute_until(&program, &HashSet::new()), Ok(BreakReason::Halted));
+        assert_eq!(vm.stack_slice(), &[10, 10]);
+    }
+
+    #[test]
+    fn execute_until_stops_before_the_first_instruction() {
+        let program = counting_loop();
+        let breakpoints = HashSet::from([0]);
+        let mut vm = VM::new();
+        assert_eq!(vm.execute_until(&program, &breakpoints), Ok(BreakReason::Breakpoint(0)));
+        assert_eq!(vm.instructions_executed(), 0);
+        assert_eq!(vm.execute_until(&program, &breakpoints), Ok(BreakReason::Halted));
+    }
+
+    #[test]
+    fn halt_records_the_exit_code() {
+        let vm = run(vec![Instruction::Halt(7), Instruction::Push(1)]);
+        assert_eq!(vm.exit_code(), Some(7));
+        assert_eq!(vm.stack_slice(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn interupt_is_halt_zero() {
+        assert_eq!(run(vec![Instruction::Interupt]).exit_code(), Some(0));
+        assert_eq!(run(vec![Instruction::Push(1)]).exit_code(), Some(0));
+        assert_eq!(VM::new().exit_code(), None);
+    }
+
+    #[test]
+    fn locals_keep_their_value() {
+        let vm = run(vec![
+            Instruction::Push(42),
+            Instruction::Store(3),
+            Instruction::Push(1),
+            Instruction::Push(2),
+            Instruction::Add,
+            Instruction::Pop,
+            Instruction::Load(3),
+        ]);
+        assert_eq!(vm.stack_slice(), &[42]);
+    }
+
+    #[test]
+    fn out_of_range_locals_are_an_error() {
+        let slot = LOCALS_SIZE as u8;
+        assert_eq!(run_err(vec![Instruction::Push(1), Instruction::Store(slot)]).kind, VmErrorKind::InvalidLocal(slot));
+        assert_eq!(run_err(vec![Instruction::Load(slot)]).kind, VmErrorKind::InvalidLocal(slot));
+    }
+
+    #[test]
+    fn small_stacks_overflow_early() {
+        let mut vm = VM::with_stack_size(4);
+        let error = vm.execute(&Program::from(vec![Instruction::Push(1); 5]), false).unwrap_err();
+        assert_eq!(error, VmError { kind: VmErrorKind::StackOverflow, at: 4 });
+        assert_eq!(vm.stack_slice(), &[1; 4]);
+    }
+
+    #[test]
+    fn large_stacks_hold_more() {
+        let mut vm = VM::with_stack_size(STACK_SIZE * 2);
+        vm.execute(&Program::from(vec![Instruction::Push(1); STACK_SIZE * 2]), false).unwrap();
+        assert_eq!(vm.stack_pointer(), STACK_SIZE * 2);
+    }
+
+    #[test]
+    fn instruction_metadata() {
+        let cases: [(Instruction, &str, usize); 8] = [
+            (Instruction::Push(1), "push", 1),
+            (Instruction::PushStr("a".to_string()), "pushstr", 1),
+            (Instruction::Pop, "pop", 0),
+            (Instruction::Add, "add", 0),
+            (Instruction::JmpEq(0), "jmpeq", 1),
+            (Instruction::StdCall(0), "stdcall", 1),
+            (Instruction::SetLocal(0, 1), "setlocal", 2),
+            (Instruction::Interupt, "interupt", 0),
+        ];
+        for (instruction, mnemonic, operands) in cases {
+            assert_eq!(instruction.mnemonic(), mnemonic);
+            assert_eq!(instruction.operand_count(), operands, "{}", mnemonic);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_out_of_bounds_jumps() {
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Jmp(7)]);
+        assert_eq!(program.validate(), Err(ValidationError::InvalidJump { index: 1, target: 7 }));
+        let program = Program::from(vec![Instruction::Call(3), Instruction::Ret]);
+        assert_eq!(program.validate(), Err(ValidationError::InvalidJump { index: 0, target: 3 }));
+    }
+
+    #[test]
+    fn validate_accepts_jumps_to_the_end() {
+        assert_eq!(counting_loop().validate(), Ok(()));
+        assert_eq!(Program::from(vec![Instruction::JmpNeq(1)]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn display_shows_each_slot_once() {
+        let vm = run((0..STACK_SIZE as u8).map(Instruction::Push).collect());
+        let text = vm.to_string();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("Program Pointer: 128"));
+        assert_eq!(lines.next(), Some("Stack [128]:"));
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len(), STACK_SIZE / 32);
+        let values: Vec<u8> = rows.iter()
+            .flat_map(|row| row.split_whitespace())
+            .map(|value| u8::from_str_radix(value, 16).unwrap())
+            .collect();
+        assert_eq!(values, (0..STACK_SIZE as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn i16_values_span_two_slots() {
+        let vm = run(vec![Instruction::PushI16(300), Instruction::PushI16(300), Instruction::AddI16]);
+        assert_eq!(vm.stack_slice(), &600u16.to_le_bytes());
+        let vm = run(vec![Instruction::PushI16(100), Instruction::PushI16(600), Instruction::SubI16]);
+        assert_eq!(vm.stack_slice(), &500u16.to_le_bytes());
+    }
+
+    #[test]
+    fn i16_ops_need_four_slots() {
+        let error = run_err(vec![Instruction::PushI16(1), Instruction::Push(1), Instruction::AddI16]);
+        assert_eq!(error.kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn snapshots_restore_the_state() {
+        let program = counting_loop();
+        let mut vm = VM::new();
+        for _ in 0..8 {
+            vm.step(&program).unwrap();
+        }
+        let snapshot = vm.to_snapshot();
+        assert_eq!(snapshot, VmSnapshot { stack_pointer: 3, program_pointer: 4, overflow: false, stack: vec![10, 1, 1] });
+        let mut restored = VM::new();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.to_snapshot(), snapshot);
+        vm.execute(&program, false).unwrap();
+        restored.execute(&program, false).unwrap();
+        assert_eq!(restored.stack_slice(), vm.stack_slice());
+    }
+
+    #[test]
+    fn inconsistent_snapshots_are_rejected() {
+        let snapshot = VmSnapshot { stack_pointer: 2, program_pointer: 0, overflow: false, stack: vec![1] };
+        assert_eq!(VM::new().restore(&snapshot).unwrap_err().kind, VmErrorKind::InvalidSnapshot);
+        let snapshot = VmSnapshot { stack_pointer: 5, program_pointer: 0, overflow: false, stack: vec![1; 5] };
+        assert_eq!(DefaultVm::with_stack_size(4).restore(&snapshot).unwrap_err().kind, VmErrorKind::StackOverflow);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshots_round_trip_through_json() {
+        let program = Program::from(vec![
+            Instruction::Push(255),
+            Instruction::Push(1),
+            Instruction::Add,
+            Instruction::JmpOverflow(5),
+            Instruction::Push(7),
+            Instruction::Push(9),
+        ]);
+        let mut vm = VM::with_output(Box::new(std::io::sink()));
+        for _ in 0..3 {
+            vm.step(&program).unwrap();
+        }
+        let json = serde_json::to_string(&vm.to_snapshot()).unwrap();
+        let snapshot: VmSnapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = VM::with_output(Box::new(std::io::sink()));
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.stack_pointer(), vm.stack_pointer());
+        assert_eq!(restored.program_counter(), vm.program_counter());
+        assert_eq!(restored.overflow(), vm.overflow());
+        assert!(restored.overflow());
+        assert_eq!(restored.stack_slice(), vm.stack_slice());
+        vm.execute(&program, false).unwrap();
+        restored.execute(&program, false).unwrap();
+        assert_eq!(restored.stack_slice(), &[0, 9]);
+        assert_eq!(restored.stack_slice(), vm.stack_slice());
+    }
+
+    #[test]
+    fn neg_is_twos_complement() {
+        assert_eq!(run(vec![Instruction::Push(5), Instruction::Neg]).stack_slice(), &[251]);
+        assert_eq!(run(vec![Instruction::Push(1), Instruction::Neg]).stack_slice(), &[255]);
+        assert_eq!(run(vec![Instruction::Push(0), Instruction::Neg]).stack_slice(), &[0]);
+        assert_eq!(run_err(vec![Instruction::Neg]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn min_and_max() {
+        assert_eq!(run(vec![Instruction::Push(3), Instruction::Push(7), Instruction::Min]).stack_slice(), &[3]);
+        assert_eq!(run(vec![Instruction::Push(3), Instruction::Push(7), Instruction::Max]).stack_slice(), &[7]);
+        assert_eq!(run(vec![Instruction::Push(7), Instruction::Push(3), Instruction::Min]).stack_slice(), &[3]);
+    }
+
+    #[test]
+    fn min_and_max_need_two_values() {
+        assert_eq!(run_err(vec![Instruction::Push(3), Instruction::Min]).kind, VmErrorKind::StackUnderflow);
+        assert_eq!(run_err(vec![Instruction::Max]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn print_hex_uses_two_digits() {
+        assert_eq!(output_of(vec![Instruction::Push(255), Instruction::StdCall(6)]), "ff");
+        assert_eq!(output_of(vec![Instruction::Push(15), Instruction::StdCall(6)]), "0f");
+    }
+
+    #[test]
+    fn depth_pushes_the_stack_size() {
+        let vm = run(vec![Instruction::Push(9), Instruction::Push(9), Instruction::Push(9), Instruction::Depth]);
+        assert_eq!(vm.stack_slice(), &[9, 9, 9, 3]);
+        assert_eq!(run(vec![Instruction::Depth]).stack_slice(), &[0]);
+    }
+
+    #[test]
+    fn depth_is_capped_for_bytes() {
+        let mut vm = VM::with_stack_size(300);
+        let mut instructions = vec![Instruction::Push(0); 280];
+        instructions.push(Instruction::Depth);
+        vm.execute(&Program::from(instructions), false).unwrap();
+        assert_eq!(vm.top(), Some(255));
+    }
+
+    #[test]
+    fn clear_stack_drops_everything() {
+        let vm = run(vec![Instruction::Push(1), Instruction::Push(2), Instruction::ClearStack, Instruction::Push(3)]);
+        assert_eq!(vm.stack_slice(), &[3]);
+    }
+
+    #[test]
+    fn reset_lets_a_vm_run_again() {
+        let mut vm = VM::new();
+        vm.execute(&Program::from(vec![Instruction::Push(255), Instruction::Push(1), Instruction::Add, Instruction::Halt(4)]), false).unwrap();
+        assert!(vm.overflow());
+        vm.reset();
+        assert_eq!((vm.stack_pointer(), vm.program_counter(), vm.overflow(), vm.exit_code()), (0, 0, false, None));
+        vm.execute(&Program::from(vec![Instruction::Push(7)]), false).unwrap();
+        assert_eq!(vm.stack_slice(), &[7]);
+        assert_eq!(vm.instructions_executed(), 2);
+    }
+
+    #[test]
+    fn hook_sees_every_instruction() {
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Jmp(3), Instruction::Pop, Instruction::Dup]);
+        let mut visited = Vec::new();
+        let mut vm = VM::new();
+        vm.execute_with_hook(&program, |pointer, instruction, vm| visited.push((pointer, instruction.clone(), vm.stack_pointer()))).unwrap();
+        assert_eq!(visited, [
+            (0, Instruction::Push(1), 0),
+            (1, Instruction::Jmp(3), 1),
+            (3, Instruction::Dup, 1),
+            (4, Instruction::Interupt, 2),
+        ]);
+    }
+
+    #[test]
+    fn instructions_are_counted() {
+        let mut vm = VM::new();
+        vm.execute(&counting_loop(), false).unwrap();
+        // Two setup pushes, ten passes through the 4-instruction loop, the exiting JmpEq and the implicit Interupt
+        assert_eq!(vm.instructions_executed(), 2 + 10 * 4 + 1 + 1);
+        vm.reset();
+        assert_eq!(vm.instructions_executed(), 0);
+    }
+
+    #[test]
+    fn push_all_keeps_the_order() {
+        let vm = run(vec![Instruction::Push(9), Instruction::PushAll(vec![1, 2, 3, 4, 5])]);
+        assert_eq!(vm.stack_slice(), &[9, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn push_all_overflow_pushes_nothing() {
+        let mut vm = VM::with_stack_size(4);
+        let error = vm.execute(&Program::from(vec![Instruction::Push(9), Instruction::PushAll(vec![1, 2, 3, 4, 5])]), false).unwrap_err();
+        assert_eq!(error, VmError { kind: VmErrorKind::StackOverflow, at: 1 });
+        assert_eq!(vm.stack_slice(), &[9]);
+    }
+
+    #[test]
+    fn iterate_over_instructions() {
+        let mut program = Program::new();
+        assert!(program.is_empty());
+        program.push(Instruction::PushStr("Hello, World!\n".to_string())).unwrap();
+        program.push(Instruction::StdCall(2)).unwrap();
+        assert_eq!(program.iter().count(), 2);
+        assert_eq!(program.len(), 2);
+        assert!(!program.is_empty());
+        assert_eq!(program.iter().last(), Some(&Instruction::StdCall(2)));
+    }
+
+    #[test]
+    fn rot_moves_the_deepest_to_the_top() {
+        let vm = run(vec![Instruction::PushAll(vec![b'a', b'b', b'c']), Instruction::Rot(3)]);
+        assert_eq!(vm.stack_slice(), b"bca");
+        let vm = run(vec![Instruction::PushAll(vec![b'a', b'b', b'c']), Instruction::Rot(1)]);
+        assert_eq!(vm.stack_slice(), b"abc");
+    }
+
+    #[test]
+    fn rot_needs_enough_values() {
+        assert_eq!(run_err(vec![Instruction::PushAll(vec![1, 2]), Instruction::Rot(3)]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn programs_can_be_collected() {
+        let program: Program = (1..=3).map(Instruction::Push).chain([Instruction::Add]).collect();
+        assert_eq!(program.len(), 4);
+        assert_eq!(run(program.into()).stack_slice(), &[1, 5]);
+    }
+
+    // Runs `jump` over (second, top) and tells whether it skipped the Push(9) after it
+    fn jump_taken(jump: fn(usize) -> Instruction, second: u8, top: u8) -> bool {
+        let vm = run(vec![Instruction::Push(second), Instruction::Push(top), jump(4), Instruction::Push(9)]);
+        assert_eq!(&vm.stack_slice()[..2], &[second, top]);
+        vm.stack_pointer() == 2
+    }
+
+    #[test]
+    fn ordered_jumps_compare_top_against_second() {
+        assert!(jump_taken(Instruction::JmpLt, 5, 3));
+        assert!(!jump_taken(Instruction::JmpLt, 3, 5));
+        assert!(!jump_taken(Instruction::JmpLt, 3, 3));
+        assert!(jump_taken(Instruction::JmpGt, 3, 5));
+        assert!(!jump_taken(Instruction::JmpGt, 5, 3));
+        assert!(jump_taken(Instruction::JmpLe, 3, 3));
+        assert!(!jump_taken(Instruction::JmpLe, 3, 5));
+        assert!(jump_taken(Instruction::JmpGe, 3, 3));
+        assert!(!jump_taken(Instruction::JmpGe, 5, 3));
+    }
+
+    #[test]
+    fn ordered_jumps_are_unsigned() {
+        assert!(jump_taken(Instruction::JmpGt, 1, 200));
+    }
+
+    #[test]
+    fn compare_and_jump_restores_the_order_either_way() {
+        for jump in [Instruction::JmpEq, Instruction::JmpNeq] {
+            let taken = run(vec![Instruction::Push(1), Instruction::Push(1), jump(3)]);
+            let not_taken = run(vec![Instruction::Push(1), Instruction::Push(2), jump(3)]);
+            assert_eq!(taken.stack_slice(), &[1, 1]);
+            assert_eq!(not_taken.stack_slice(), &[1, 2]);
+        }
+        assert!(jump_taken(Instruction::JmpEq, 4, 4));
+        assert!(!jump_taken(Instruction::JmpEq, 4, 5));
+        assert!(jump_taken(Instruction::JmpNeq, 4, 5));
+        assert!(!jump_taken(Instruction::JmpNeq, 4, 4));
+    }
+
+    #[test]
+    fn to_bool_normalizes() {
+        assert_eq!(run(vec![Instruction::Push(200), Instruction::ToBool]).stack_slice(), &[1]);
+        assert_eq!(run(vec![Instruction::Push(0), Instruction::ToBool]).stack_slice(), &[0]);
+        assert_eq!(run(vec![Instruction::Push(1), Instruction::ToBool]).stack_slice(), &[1]);
+    }
+
+    #[test]
+    fn truthiness_jumps_pop_the_condition() {
+        let program = |value, jump: fn(usize) -> Instruction| vec![Instruction::Push(value), jump(3), Instruction::Push(9)];
+        assert_eq!(run(program(5, Instruction::JmpIf)).stack_slice(), &[] as &[u8]);
+        assert_eq!(run(program(0, Instruction::JmpIf)).stack_slice(), &[9]);
+        assert_eq!(run(program(0, Instruction::JmpIfNot)).stack_slice(), &[] as &[u8]);
+        assert_eq!(run(program(5, Instruction::JmpIfNot)).stack_slice(), &[9]);
+        assert_eq!(run_err(vec![Instruction::JmpIf(0)]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn memory_reads_back_what_was_written() {
+        let vm = run(vec![
+            Instruction::Push(11), Instruction::Push(0), Instruction::MemStore,
+            Instruction::Push(22), Instruction::Push(100), Instruction::MemStore,
+            Instruction::Push(33), Instruction::Push(255), Instruction::MemStore,
+            Instruction::Push(255), Instruction::MemLoad,
+            Instruction::Push(0), Instruction::MemLoad,
+            Instruction::Push(100), Instruction::MemLoad,
+            Instruction::Push(1), Instruction::MemLoad,
+        ]);
+        assert_eq!(vm.stack_slice(), &[33, 11, 22, 0]);
+    }
+
+    #[test]
+    fn memory_accesses_are_bounds_checked() {
+        let mut vm = VM::with_memory(8);
+        let error = vm.execute(&Program::from(vec![Instruction::Push(1), Instruction::Push(8), Instruction::MemStore]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::OutOfBoundsMemory(8));
+        let mut vm = VM::with_memory(8);
+        let error = vm.execute(&Program::from(vec![Instruction::Push(200), Instruction::MemLoad]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::OutOfBoundsMemory(200));
+    }
+
+    // Writes `bytes` into memory starting at `address`
+    fn store_bytes(address: u8, bytes: &[u8]) -> Vec<Instruction> {
+        bytes.iter().zip(address..).flat_map(|(byte, address)| [Instruction::Push(*byte), Instruction::Push(address), Instruction::MemStore]).collect()
+    }
+
+    #[test]
+    fn print_mem_string_reads_from_memory() {
+        let output = SharedOutput::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        let mut instructions = vec![Instruction::Push(7)];
+        instructions.extend(store_bytes(10, b"Hi\0"));
+        instructions.extend([Instruction::Push(10), Instruction::StdCall(7)]);
+        vm.execute(&Program::from(instructions), false).unwrap();
+        assert_eq!(&*output.0.borrow(), b"Hi");
+        assert_eq!(vm.stack_slice(), &[7]);
+    }
+
+    #[test]
+    fn print_mem_string_needs_a_terminator() {
+        let mut vm = VM::with_memory(4);
+        let mut instructions = store_bytes(0, b"abcd");
+        instructions.extend([Instruction::Push(0), Instruction::StdCall(7)]);
+        let error = vm.execute(&Program::from(instructions), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::MissingStringTerminator);
+    }
+
+    #[test]
+    fn u32_cells_hold_wide_values() {
+        let program = Program::<u32>::from_iter([Instruction::Push(100000), Instruction::Push(100000), Instruction::Add]);
+        let mut vm = VM::<u32>::with_cells();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.top(), Some(200000));
+        assert!(!vm.overflow());
+    }
+
+    #[test]
+    fn default_vm_keeps_byte_cells() {
+        let mut vm = DefaultVm::new();
+        vm.execute(&Program::from(vec![Instruction::Push(200), Instruction::Push(100), Instruction::Add]), false).unwrap();
+        assert_eq!(vm.top(), Some(44));
+        assert!(vm.overflow());
+    }
+
+    #[test]
+    fn untyped_programs_default_to_bytes() {
+        let mut program = Program::new();
+        program.push(Instruction::Push(10)).unwrap();
+        VM::new().execute(&program, false).unwrap();
+        VM::default().execute(&Program::from(vec![Instruction::Push(1)]), false).unwrap();
+        Program::default().push(Instruction::Push(1)).unwrap();
+    }
+
+    #[test]
+    fn wide_vms_use_the_same_constructors() {
+        let program = Program::<u16>::from_iter([Instruction::Push(300), Instruction::StdCall(0)]);
+        let output = SharedOutput::default();
+        let mut vm = VM::<u16>::with_output(Box::new(output.clone()));
+        vm.execute(&program, false).unwrap();
+        assert_eq!(output.0.borrow().as_slice(), b"300");
+        let mut vm = VM::<u16>::with_stack_size(1);
+        let error = vm.execute(&Program::from_iter([Instruction::Push(1), Instruction::Push(2)]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::StackOverflow);
+        assert_eq!(VM::<u16>::with_fuel(5).fuel(), Some(5));
+    }
+
+    #[test]
+    fn pick_copies_from_depth() {
+        let vm = run(vec![Instruction::PushAll(vec![1, 2, 3]), Instruction::Pick(0), Instruction::Pick(2)]);
+        assert_eq!(vm.stack_slice(), &[1, 2, 3, 3, 2]);
+    }
+
+    #[test]
+    fn pick_past_the_bottom_underflows() {
+        assert_eq!(run_err(vec![Instruction::Push(1), Instruction::Pick(1)]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn drop_n_pops_several() {
+        let vm = run(vec![Instruction::PushAll(vec![1, 2, 3, 4, 5]), Instruction::DropN(3)]);
+        assert_eq!(vm.stack_slice(), &[1, 2]);
+    }
+
+    #[test]
+    fn drop_n_checks_the_count_first() {
+        let mut vm = VM::new();
+        let error = vm.execute(&Program::from(vec![Instruction::Push(1), Instruction::DropN(2)]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::StackUnderflow);
+        assert_eq!(vm.stack_slice(), &[1]);
+    }
+
+    #[test]
+    fn inc_wraps_and_sets_overflow() {
+        let vm = run(vec![Instruction::Push(255), Instruction::Inc]);
+        assert_eq!(vm.stack_slice(), &[0]);
+        assert!(vm.overflow());
+        let vm = run(vec![Instruction::Push(4), Instruction::Inc]);
+        assert_eq!(vm.stack_slice(), &[5]);
+        assert!(!vm.overflow());
+    }
+
+    #[test]
+    fn dec_wraps_and_sets_overflow() {
+        let vm = run(vec![Instruction::Push(0), Instruction::Dec]);
+        assert_eq!(vm.stack_slice(), &[255]);
+        assert!(vm.overflow());
+    }
+
+    #[test]
+    fn execute_validated_rejects_before_running() {
+        let mut vm = VM::new();
+        let program = Program::from(vec![Instruction::Jmp(999), Instruction::Push(1)]);
+        let error = vm.execute_validated(&program, false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::Validation(ValidationError::InvalidJump { index: 0, target: 999 }));
+        assert_eq!(vm.instructions_executed(), 0);
+    }
+
+    #[test]
+    fn execute_validated_runs_valid_programs() {
+        let mut vm = VM::new();
+        vm.execute_validated(&counting_loop(), false).unwrap();
+        assert_eq!(vm.stack_slice(), &[10, 10]);
+    }
+
+    #[test]
+    fn push_char_pushes_its_byte() {
+        assert_eq!(run(vec![Instruction::PushChar('Z'), Instruction::PushChar('é')]).stack_slice(), &[b'Z', 0xe9]);
+    }
+
+    #[test]
+    fn push_char_rejects_wide_chars() {
+        assert_eq!(run_err(vec![Instruction::PushChar('€')]).kind, VmErrorKind::InvalidChar('€'));
+    }
+
+    #[test]
+    fn nop_only_advances_the_pointer() {
+        let mut program = Program::new();
+        program.push_u8(7).nop();
+        let mut vm = VM::new();
+        vm.step(&program).unwrap();
+        assert_eq!(vm.step(&program), Ok(StepOutcome::Continued));
+        assert_eq!(vm.program_counter(), 2);
+        assert_eq!(vm.stack_slice(), &[7]);
+    }
+
+    #[test]
+    fn set_backpatches_a_forward_jump() {
+        let mut program = Program::new();
+        program.push_u8(1);
+        let jump = program.len();
+        program.jmp(0).push_u8(2);
+        program.set(jump, Instruction::Jmp(program.len())).unwrap();
+        assert_eq!(program.set(10, Instruction::Nop), Err(()));
+        assert_eq!(run(program.into()).stack_slice(), &[1]);
+    }
+
+    #[test]
+    fn recursive_factorial_uses_frames() {
+        let program = Program::parse("
+            push 5
+            call fact
+            halt 0
+        fact:
+            loadarg 0
+            jmpif recurse
+            push 1
+            storearg 0
+            ret
+        recurse:
+            loadarg 0
+            dec
+            call fact
+            loadarg 0
+            mul
+            storearg 0
+            ret
+        ").unwrap();
+        let mut vm = VM::new();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[120]);
+    }
+
+    #[test]
+    fn load_arg_outside_a_frame_underflows() {
+        assert_eq!(run_err(vec![Instruction::LoadArg(0)]).kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn add_carry_chains_the_low_byte_into_the_high_byte() {
+        // 0x01f0 + 0x0220 = 0x0410, high bytes pushed first then low bytes
+        let mut program = Program::new();
+        program.push_all([0x01, 0x02, 0xf0, 0x20]).add().rot(3).rot(3).add_carry();
+        assert_eq!(run(program.into()).stack_slice(), &[0x10, 0x04]);
+    }
+
+    #[test]
+    fn sub_borrow_chains_the_low_byte_into_the_high_byte() {
+        // 0x0410 - 0x0220 = 0x01f0, the minuend is on top
+        let mut program = Program::new();
+        program.push_all([0x02, 0x04, 0x20, 0x10]).sub().rot(3).rot(3).sub_borrow();
+        assert_eq!(run(program.into()).stack_slice(), &[0xf0, 0x01]);
+    }
+
+    #[test]
+    fn errors_report_the_failing_instruction() {
+        let mut program = Program::new();
+        program.push_u8(1).push_u8(2).add().nop().add();
+        let error = run_err(program.into());
+        assert_eq!(error, VmError { kind: VmErrorKind::StackUnderflow, at: 4 });
+        assert_eq!(error.to_string(), "error at instruction 4: stack underflow");
+    }
+
+    #[test]
+    fn program_converts_into_its_instructions() {
+        let mut program = Program::new();
+        program.push_u8(1).pop();
+        assert_eq!(program.as_slice(), &[Instruction::Push(1), Instruction::Pop]);
+        let instructions: Vec<Instruction> = program.into();
+        assert_eq!(instructions, vec![Instruction::Push(1), Instruction::Pop]);
+    }
+
+    #[test]
+    fn recording_logs_std_calls() {
+        let mut program = Program::new();
+        program.push_str("Hi\n").std_call(StdFunc::PrintString).push_u8(3).std_call(StdFunc::Clone);
+        let mut vm = VM::with_recording();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.records(), &[
+            StdCallRecord { func: StdFunc::PrintString, consumed: vec![b'H', b'i', b'\n', 0], output: "Hi\n".to_string() },
+            StdCallRecord { func: StdFunc::Clone, consumed: vec![], output: String::new() },
+        ]);
+    }
+
+    #[test]
+    fn records_are_empty_without_recording() {
+        assert!(run(vec![Instruction::PushStr("a".to_string()), Instruction::StdCall(2)]).records().is_empty());
+    }
+
+    #[test]
+    fn push_str_keeps_multibyte_characters() {
+        let mut program = Program::new();
+        program.push_str("héllo");
+        assert_eq!(run(program.clone().into()).stack_pointer(), 7);
+        program.std_call(StdFunc::PrintString);
+        let mut vm = VM::with_recording();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.records()[0].output, "héllo");
+        assert_eq!(vm.stack_pointer(), 0);
+    }
+
+    #[test]
+    fn print_mem_string_decodes_utf8() {
+        let mut instructions = store_bytes(0, "é\0".as_bytes());
+        instructions.extend([Instruction::Push(0), Instruction::StdCall(7)]);
+        assert_eq!(output_of(instructions), "é");
+        let mut instructions = store_bytes(0, b"a\xff\0");
+        instructions.extend([Instruction::Push(0), Instruction::StdCall(7)]);
+        assert_eq!(output_of(instructions), "a\u{fffd}");
+    }
+
+    #[test]
+    fn checksum_sums_the_stack_without_consuming_it() {
+        let instructions = vec![Instruction::Push(1), Instruction::Push(2), Instruction::Push(3), Instruction::StdCall(8)];
+        assert_eq!(run(instructions).stack_slice(), &[1, 2, 3, 6]);
+        assert_eq!(run(vec![Instruction::Push(200), Instruction::Push(100), Instruction::StdCall(8)]).top(), Some(44));
+    }
+
+    #[test]
+    fn execute_timed_stops_an_infinite_loop() {
+        let mut vm = VM::new();
+        let error = vm.execute_timed(&Program::from(vec![Instruction::Jmp(0)]), std::time::Duration::from_millis(20)).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::TimeoutExceeded);
+    }
+
+    #[test]
+    fn execute_timed_finishes_short_programs() {
+        let mut vm = VM::new();
+        vm.execute_timed(&Program::from(vec![Instruction::Push(1)]), std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(vm.stack_slice(), &[1]);
+    }
+
+    #[test]
+    fn print_u8_ln_appends_a_newline() {
+        assert_eq!(output_of(vec![Instruction::Push(42), Instruction::StdCall(9)]), "42\n");
+    }
+
+    #[test]
+    fn high_water_mark_tracks_the_peak_depth() {
+        let mut program = Program::new();
+        program.push_all([1, 2, 3]).pop().pop().push_u8(1);
+        let mut vm = VM::new();
+        vm.set_watermark(3);
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.high_water_mark(), 3);
+        assert!(!vm.watermark_exceeded());
+    }
+
+    #[test]
+    fn watermark_is_flagged_and_reset() {
+        let mut program = Program::new();
+        program.push_all([1, 2, 3, 4]).drop_n(4);
+        let mut vm = VM::new();
+        vm.set_watermark(3);
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.high_water_mark(), 4);
+        assert!(vm.watermark_exceeded());
+        vm.reset();
+        assert_eq!(vm.high_water_mark(), 0);
+    }
+
+    #[test]
+    fn cmp_orders_the_top_against_the_second() {
+        for (second, top, expected) in [(5, 3, 255), (3, 3, 0), (3, 5, 1)] {
+            let vm = run(vec![Instruction::Push(second), Instruction::Push(top), Instruction::Cmp]);
+            assert_eq!(vm.stack_slice(), &[expected]);
+        }
+    }
+
+    #[test]
+    fn execute_all_carries_the_stack_over() {
+        let first = Program::from(vec![Instruction::Push(2), Instruction::Push(3)]);
+        let second = Program::from(vec![Instruction::Add]);
+        let mut vm = VM::new();
+        vm.execute_all(&[first, second]).unwrap();
+        assert_eq!(vm.stack_slice(), &[5]);
+    }
+
+    #[test]
+    fn assert_passes_on_the_expected_value() {
+        assert_eq!(run(vec![Instruction::Push(5), Instruction::Assert(5)]).stack_pointer(), 0);
+    }
+
+    #[test]
+    fn assert_reports_the_mismatch() {
+        let error = run_err(vec![Instruction::Push(4), Instruction::Assert(5)]);
+        assert_eq!(error, VmError { kind: VmErrorKind::AssertionFailed { expected: 5, got: 4 }, at: 1 });
+    }
+
+    #[test]
+    fn assert_reports_the_full_wide_cell() {
+        let mut vm = VM::<u32>::with_cells();
+        let error = vm.execute(&Program::from_iter([Instruction::Push(0x105), Instruction::Assert(5)]), false).unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::AssertionFailed { expected: 5, got: 0x105 });
+        assert_eq!(error.kind.to_string(), "assertion failed: expected 5, got 261");
+    }
+
+    #[test]
+    fn program_counter_can_be_restored() {
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Push(3), Instruction::Push(4)]);
+        let mut vm = VM::new();
+        vm.step(&program).unwrap();
+        vm.step(&program).unwrap();
+        let pc = vm.program_counter();
+        assert_eq!(pc, 2);
+        vm.reset();
+        vm.set_program_counter(pc, Some(program.len())).unwrap();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[3, 4]);
+    }
+
+    #[test]
+    fn set_program_counter_checks_the_length() {
+        let mut vm = VM::new();
+        assert_eq!(vm.set_program_counter(9, Some(4)).unwrap_err().kind, VmErrorKind::InvalidJump(9));
+        vm.set_program_counter(9, None).unwrap();
+        assert_eq!(vm.program_counter(), 9);
+    }
+
+    fn signed_div_mod(dividend: i8, divisor: i8) -> (i8, i8) {
+        let (dividend, divisor) = (dividend as u8, divisor as u8);
+        let vm = run(vec![
+            Instruction::Push(divisor), Instruction::Push(dividend), Instruction::DivI,
+            Instruction::Push(divisor), Instruction::Push(dividend), Instruction::ModI,
+        ]);
+        (vm.stack_slice()[0] as i8, vm.stack_slice()[1] as i8)
+    }
+
+    #[test]
+    fn signed_division_truncates() {
+        assert_eq!(signed_div_mod(-7, 2), (-3, -1));
+        assert_eq!(signed_div_mod(7, -2), (-3, 1));
+        assert_eq!(signed_div_mod(-7, -2), (3, -1));
+        assert_eq!(signed_div_mod(7, 2), (3, 1));
+        assert_eq!(signed_div_mod(-128, -1), (-128, 0));
+    }
+
+    #[test]
+    fn signed_modulo_by_zero_is_an_error() {
+        assert_eq!(run_err(vec![Instruction::Push(0), Instruction::Push(7), Instruction::ModI]).kind, VmErrorKind::DivideByZero);
+    }
+
+    #[test]
+    fn counted_strings_keep_embedded_nulls() {
+        let mut program = Program::new();
+        program.push_u8(9).push_counted_str("a\0b").std_call(StdFunc::PrintCountedString);
+        let mut vm = VM::with_recording();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.records()[0].output, "a\0b");
+        assert_eq!(vm.stack_slice(), &[9]);
+    }
+
+    #[test]
+    fn counted_strings_are_limited_to_a_byte() {
+        let error = run_err(vec![Instruction::PushCountedStr("x".repeat(300))]);
+        assert_eq!(error.kind, VmErrorKind::StringTooLong(300));
+    }
+
+    #[test]
+    fn print_counted_string_needs_the_whole_string() {
+        let error = run_err(vec![Instruction::Push(3), Instruction::StdCall(0xD)]);
+        assert_eq!(error.kind, VmErrorKind::StackUnderflow);
+    }
+
+    #[test]
+    fn execute_slice_runs_a_raw_array() {
+        use Instruction::*;
+        let code = [Push(10), Push(0), JmpEq(6), Push(1), Add, Jmp(2)];
+        let mut vm = VM::new();
+        vm.execute_slice(&code, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[10, 10]);
+        let mut vm = VM::new();
+        assert_eq!(vm.execute_slice_limited(&code, 5).unwrap_err().kind, VmErrorKind::StepLimitExceeded);
+    }
+
+    #[test]
+    fn execute_slice_checks_jumps() {
+        assert_eq!(VM::new().execute_slice(&[Instruction::Jmp(7)], false).unwrap_err().kind, VmErrorKind::InvalidJump(7));
+    }
+
+    #[test]
+    fn dup_n_copies_a_window() {
+        let vm = run(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Push(3), Instruction::DupN(2)]);
+        assert_eq!(vm.stack_slice(), &[1, 2, 3, 2, 3]);
+    }
+
+    #[test]
+    fn dup_n_checks_both_ends_of_the_stack() {
+        assert_eq!(run_err(vec![Instruction::Push(1), Instruction::DupN(2)]).kind, VmErrorKind::StackUnderflow);
+        let mut vm = VM::with_stack_size(4);
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Push(3), Instruction::DupN(2)]);
+        assert_eq!(vm.execute(&program, false).unwrap_err().kind, VmErrorKind::StackOverflow);
+        assert_eq!(vm.stack_slice(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn capped_programs_reject_the_extra_push() {
+        let mut program = Program::with_max_len(8);
+        for _ in 0..8 {
+            program.push(Instruction::Nop).unwrap();
+        }
+        assert_eq!(program.push(Instruction::Nop), Err(ProgramFull { max_len: 8 }));
+        assert_eq!(program.len(), 8);
+    }
+
+    #[test]
+    fn default_programs_are_unbounded() {
+        let mut program = Program::new();
+        for _ in 0..1000 {
+            program.push(Instruction::Nop).unwrap();
+        }
+        assert_eq!(program.len(), 1000);
+    }
+
+    #[test]
+    fn extending_past_the_cap_marks_the_program_full() {
+        let mut program = Program::with_max_len(1);
+        program.extend([Instruction::Nop, Instruction::Nop]);
+        assert_eq!(program.as_slice(), &[Instruction::Nop]);
+        assert_eq!(program.validate(), Err(ValidationError::ProgramFull { max_len: 1 }));
+        assert_eq!(program.finalize(), Err(ValidationError::ProgramFull { max_len: 1 }));
+    }
+
+    #[test]
+    fn eval_returns_the_top_of_the_stack() {
+        assert_eq!(VM::eval(&Program::from(vec![Instruction::Push(3), Instruction::Push(4), Instruction::Add])), Ok(Some(7)));
+        assert_eq!(VM::eval(&Program::from(vec![Instruction::Nop])), Ok(None));
+    }
+
+    #[test]
+    fn eval_stops_infinite_loops() {
+        assert_eq!(VM::eval(&Program::from(vec![Instruction::Jmp(0)])).unwrap_err().kind, VmErrorKind::StepLimitExceeded);
+    }
+
+    #[test]
+    fn last_instruction_follows_execution() {
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Push(2), Instruction::Add, Instruction::Pop, Instruction::Pop]);
+        let mut vm = VM::new();
+        assert_eq!(vm.last_instruction(&program), None);
+        vm.step(&program).unwrap();
+        vm.step(&program).unwrap();
+        assert_eq!(vm.last_instruction(&program), Some(Instruction::Push(2)));
+        vm.step(&program).unwrap();
+        assert_eq!(vm.last_instruction(&program), Some(Instruction::Add));
+        assert!(vm.execute(&program, false).is_err());
+        assert_eq!(vm.last_instruction(&program), Some(Instruction::Pop));
+        assert_eq!(vm.last_instruction_index(), Some(4));
+        vm.reset();
+        assert_eq!(vm.last_instruction(&program), None);
+    }
+
+    #[test]
+    fn to_r_and_from_r_move_values_across() {
+        let vm = run(vec![Instruction::Push(5), Instruction::Push(7), Instruction::ToR, Instruction::Push(1), Instruction::Add, Instruction::FromR]);
+        assert_eq!(vm.stack_slice(), &[6, 7]);
+    }
+
+    #[test]
+    fn return_stack_moves_are_bounds_checked() {
+        assert_eq!(run_err(vec![Instruction::FromR]).kind, VmErrorKind::CallStackUnderflow);
+        assert_eq!(run_err(vec![Instruction::ToR]).kind, VmErrorKind::StackUnderflow);
+        let mut vm = VM::new();
+        vm.set_max_call_depth(1);
+        let program = Program::from(vec![Instruction::Push(1), Instruction::Push(2), Instruction::ToR, Instruction::ToR]);
+        assert_eq!(vm.execute(&program, false).unwrap_err().kind, VmErrorKind::CallStackOverflow);
+        assert_eq!(vm.stack_slice(), &[1]);
+        let mut vm = VM::with_stack_size(1);
+        let program = Program::from(vec![Instruction::Push(1), Instruction::ToR, Instruction::Push(2), Instruction::FromR]);
+        assert_eq!(vm.execute(&program, false).unwrap_err().kind, VmErrorKind::StackOverflow);
+    }
+
+    fn report_of(instructions: Vec<Instruction>) -> String {
+        let mut vm = VM::with_output(Box::new(std::io::sink()));
+        let program = Program::from(instructions);
+        let error = vm.execute(&program, false).unwrap_err();
+        error.report(&vm, &program)
+    }
+
+    #[test]
+    fn report_shows_the_instruction_and_stack() {
+        let report = report_of(vec![Instruction::Push(1), Instruction::Push(0), Instruction::Push(0x2a), Instruction::Div]);
+        assert_eq!(report, "divide by zero at #3 (div), stack: [01]");
+        let report = report_of(vec![Instruction::Push(1), Instruction::Pop, Instruction::Pop]);
+        assert_eq!(report, "stack underflow at #2 (pop), stack: []");
+    }
+
+    #[test]
+    fn report_keeps_the_operand_of_a_failed_pair_pop() {
+        assert_eq!(report_of(vec![Instruction::Push(5), Instruction::Add]), "stack underflow at #1 (add), stack: [05]");
+    }
+
+    #[test]
+    fn report_truncates_deep_stacks() {
+        let mut instructions = vec![Instruction::Push(1); 10];
+        instructions.extend([Instruction::Push(0), Instruction::Swap, Instruction::Mod]);
+        assert_eq!(report_of(instructions), "divide by zero at #12 (mod), stack: [.. 01 01 01 01 01 01 01 01]");
+    }
+
+    #[test]
+    fn running_out_of_fuel_stops_the_program() {
+        let mut vm = VM::with_fuel(20);
+        assert_eq!(vm.execute(&counting_loop(), false).unwrap_err().kind, VmErrorKind::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(0));
+        let mut vm = VM::with_fuel(500);
+        vm.execute(&counting_loop(), false).unwrap();
+        assert_eq!(vm.fuel(), Some(500 - 2 - 11 - 30));
+    }
+
+    #[test]
+    fn fuel_is_charged_before_executing() {
+        let mut vm = VM::with_fuel(3);
+        let program = Program::from(vec![Instruction::PushStr("abc".to_string())]);
+        assert_eq!(vm.execute(&program, false).unwrap_err().kind, VmErrorKind::OutOfFuel);
+        assert_eq!(vm.stack_pointer(), 0);
+        let mut vm = VM::with_fuel(1);
+        vm.execute(&Program::from(vec![Instruction::Nop]), false).unwrap();
+        assert_eq!(Instruction::<u8>::StdCall(0).cost(), 10);
+        assert_eq!(VM::new().fuel(), None);
+    }
+
+    #[test]
+    fn print_stack_dumps_without_consuming() {
+        let instructions = vec![Instruction::Push(1), Instruction::Push(0x2a), Instruction::StdCall(0xA), Instruction::Push(0xff), Instruction::StdCall(0xA)];
+        let mut vm = VM::with_recording();
+        vm.execute(&Program::from(instructions), false).unwrap();
+        assert_eq!(vm.records()[0].output, "Stack [2]:\n01 2a \n");
+        assert_eq!(vm.records()[1].output, "Stack [3]:\n01 2a ff \n");
+        assert!(vm.records()[1].consumed.is_empty());
+        assert_eq!(vm.stack_slice(), &[1, 0x2a, 0xff]);
+    }
+
+    // Counts `slot` up to `limit` with absolute jumps
+    fn count_to(slot: u8, limit: u8) -> Program {
+        let mut program = Program::new();
+        program.push_u8(limit).load(slot).jmp_eq(7).push_u8(1).add().store(slot).jmp(1).pop().pop();
+        program
+    }
+
+    #[test]
+    fn extend_from_relocates_jumps() {
+        let mut both = count_to(0, 3);
+        both.extend_from(&count_to(1, 5)).unwrap();
+        assert_eq!(both.get(11), Instruction::JmpEq(16));
+        assert_eq!(both.get(15), Instruction::Jmp(10));
+        assert_eq!(both.validate(), Ok(()));
+        both.load(0).load(1);
+        assert_eq!(run(both.into()).stack_slice(), &[3, 5]);
+    }
+
+    #[test]
+    fn extend_from_respects_the_cap() {
+        let mut capped = Program::with_max_len(12);
+        capped.extend_from(&count_to(0, 3)).unwrap();
+        assert_eq!(capped.extend_from(&count_to(0, 3)), Err(ExtendError::Full(ProgramFull { max_len: 12 })));
+        assert_eq!(capped.len(), 9);
+    }
+
+    #[test]
+    fn extend_from_carries_pending_labels() {
+        let mut tail = Program::new();
+        tail.nop().label("end").jmp_to("end");
+        let mut program = Program::new();
+        program.nop();
+        program.extend_from(&tail).unwrap();
+        program.finalize().unwrap();
+        assert_eq!(program.as_slice(), &[Instruction::Nop, Instruction::Nop, Instruction::Jmp(2)]);
+    }
+
+    #[test]
+    fn extend_from_rejects_labels_defined_twice() {
+        let mut head = Program::new();
+        head.label("start").nop().label("end");
+        let mut tail = Program::new();
+        tail.nop().label("end").jmp_to("end");
+        assert_eq!(head.extend_from(&tail), Err(ExtendError::DuplicateLabel("end".to_string())));
+        assert_eq!(head.len(), 1);
+        assert_eq!(head.labels.get("end"), Some(&1));
+        assert_eq!(ExtendError::DuplicateLabel("end".to_string()).to_string(), "label 'end' is defined in both programs");
+    }
+
+    // Counts from 0 to 10 with a backward relative jump
+    fn relative_loop() -> Program {
+        let mut program = Program::new();
+        program.push_u8(10).push_u8(0).jmp_eq_rel(4).push_u8(1).add().jmp_rel(-3);
+        program
+    }
+
+    #[test]
+    fn relative_jumps_loop_backwards() {
+        assert_eq!(run(relative_loop().into()).stack_slice(), &[10, 10]);
+        assert_eq!(relative_loop().validate(), Ok(()));
+    }
+
+    #[test]
+    fn relative_jumps_survive_relocation() {
+        let mut moved = Program::from(vec![Instruction::Nop; 3]);
+        moved.extend(relative_loop().iter().cloned());
+        assert_eq!(run(moved.into()).stack_slice(), &[10, 10]);
+    }
+
+    #[test]
+    fn relative_jumps_stay_in_range() {
+        let program = Program::from(vec![Instruction::Nop, Instruction::JmpRel(-2)]);
+        assert_eq!(run_err(program.clone().into()), VmError { kind: VmErrorKind::InvalidRelativeJump(-2), at: 1 });
+        assert_eq!(program.validate(), Err(ValidationError::InvalidRelativeJump { index: 1, offset: -2 }));
+        assert_eq!(run_err(vec![Instruction::JmpRel(2)]).kind, VmErrorKind::InvalidRelativeJump(2));
+        // Jumping to the end of the program is allowed, like falling through
+        run(vec![Instruction::JmpRel(1)]);
+    }
+
+    #[test]
+    fn cycles_pushes_the_instruction_count() {
+        let vm = run(vec![Instruction::StdCall(0xB), Instruction::Nop, Instruction::Nop, Instruction::Nop, Instruction::StdCall(0xB)]);
+        assert_eq!(vm.stack_slice(), &[1, 5]);
+    }
+
+    fn print_in_radix(radix: u32) -> String {
+        let output = SharedOutput::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        vm.set_print_radix(radix).unwrap();
+        let program = Program::from(vec![Instruction::Push(255), Instruction::StdCall(0), Instruction::Push(8), Instruction::StdCall(9)]);
+        vm.execute(&program, false).unwrap();
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn print_u8_respects_the_radix() {
+        assert_eq!(print_in_radix(2), "111111111000\n");
+        assert_eq!(print_in_radix(8), "37710\n");
+        assert_eq!(print_in_radix(10), "2558\n");
+        assert_eq!(print_in_radix(16), "ff8\n");
+    }
+
+    #[test]
+    fn unsupported_radices_are_rejected() {
+        assert_eq!(VM::new().set_print_radix(3), Err(InvalidRadix { radix: 3 }));
+        assert_eq!(InvalidRadix { radix: 3 }.to_string(), "unsupported print radix 3");
+    }
+
+    struct ExplodingOutput;
+
+    impl Write for ExplodingOutput {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            panic!("writer exploded");
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn execute_caught_turns_panics_into_errors() {
+        let mut vm = VM::with_output(Box::new(ExplodingOutput));
+        let program = Program::from(vec![Instruction::Push(16), Instruction::Push(16), Instruction::Mul, Instruction::StdCall(0)]);
+        let error = vm.execute_caught(&program).unwrap_err();
+        assert_eq!(error, VmError { kind: VmErrorKind::Panic("writer exploded".to_string()), at: 3 });
+    }
+
+    #[test]
+    fn mul_wraps_instead_of_panicking() {
+        let mut vm = VM::new();
+        vm.execute_caught(&Program::from(vec![Instruction::Push(16), Instruction::Push(16), Instruction::Mul])).unwrap();
+        assert_eq!(vm.stack_slice(), &[0]);
+        assert!(vm.overflow());
+    }
+
+    fn random_bytes(mut vm: VM) -> Vec<u8> {
+        vm.execute(&Program::from(vec![Instruction::StdCall(0xC); 4]), false).unwrap();
+        vm.stack_slice().to_vec()
+    }
+
+    #[test]
+    fn rand_is_deterministic_per_seed() {
+        let bytes = random_bytes(VM::with_seed(42));
+        assert_eq!(bytes, random_bytes(VM::with_seed(42)));
+        assert_ne!(bytes, random_bytes(VM::with_seed(43)));
+        assert_eq!(random_bytes(VM::with_seed(0)), random_bytes(VM::new()));
+    }
+
+    #[test]
+    fn reset_restarts_the_sequence() {
+        let program = Program::from(vec![Instruction::StdCall(0xC); 4]);
+        let mut vm = VM::with_seed(42);
+        vm.execute(&program, false).unwrap();
+        vm.reset();
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &random_bytes(VM::with_seed(42))[..]);
+    }
+
+    #[test]
+    fn overflow_flag_is_visible_to_the_host() {
+        assert!(run(vec![Instruction::Push(255), Instruction::Push(1), Instruction::Add]).overflow());
+        assert!(!run(vec![Instruction::Push(254), Instruction::Push(1), Instruction::Add]).overflow());
+    }
+
+    #[test]
+    fn overflow_flag_can_be_set_by_the_host() {
+        let program = Program::from(vec![Instruction::JmpOverflow(3), Instruction::Push(1), Instruction::Halt(0), Instruction::Push(2)]);
+        let mut vm = VM::new();
+        vm.set_overflow(true);
+        vm.execute(&program, false).unwrap();
+        assert_eq!(vm.stack_slice(), &[2]);
+    }
+
+    #[test]
+    fn set_local_writes_without_the_stack() {
+        let vm = run(vec![Instruction::SetLocal(2, 77), Instruction::Load(2)]);
+        assert_eq!(vm.stack_slice(), &[77]);
+        assert_eq!(Instruction::<u8>::SetLocal(2, 77).operand_count(), 2);
+    }
+
+    #[test]
+    fn set_local_checks_the_slot() {
+        assert_eq!(run_err(vec![Instruction::SetLocal(16, 1)]).kind, VmErrorKind::InvalidLocal(16));
+    }
+
+    #[test]
+    fn set_local_holds_wide_cells() {
+        let mut vm = VM::<u16>::with_cells();
+        vm.execute(&Program::from_iter([Instruction::SetLocal(0, 1000), Instruction::Load(0)]), false).unwrap();
+        assert_eq!(vm.stack_slice(), &[1000]);
+    }
+
+    #[test]
+    fn case_conversion_only_touches_ascii_letters() {
+        let vm = run(vec![
+            Instruction::Push(b'a'), Instruction::ToUpper,
+            Instruction::Push(b'7'), Instruction::ToUpper,
+            Instruction::Push(b'Q'), Instruction::ToLower,
+            Instruction::Push(b'z'), Instruction::ToLower,
+            Instruction::Push(0xe9), Instruction::ToUpper,
+        ]);
+        assert_eq!(vm.stack_slice(), b"A7qz\xe9");
+    }
+
+    #[test]
+    fn case_conversion_leaves_wide_cells_alone() {
+        let mut vm = VM::<u16>::with_cells();
+        vm.execute(&Program::from_iter([Instruction::Push(0x161), Instruction::ToUpper, Instruction::Push(0x61), Instruction::ToUpper]), false).unwrap();
+        assert_eq!(vm.stack_slice(), &[0x161, 0x41]);
+    }
+
+    #[test]
+    fn case_conversion_on_empty_stack_underflows() {
+        assert_eq!(run_err(vec![Instruction::ToLower]).kind, VmErrorKind::StackUnderflow);
+    }
+}
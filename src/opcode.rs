@@ -0,0 +1,312 @@
+use crate::Instruction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Opcode {
+    Push = 0x00,
+    PushStr = 0x01,
+    Pop = 0x02,
+    Dup = 0x03,
+    Swap = 0x04,
+    Over = 0x05,
+    Add = 0x06,
+    Sub = 0x07,
+    Mul = 0x08,
+    Div = 0x09,
+    Mod = 0x0a,
+    AddI = 0x0b,
+    SubI = 0x0c,
+    MulI = 0x0d,
+    DivI = 0x0e,
+    Lt = 0x0f,
+    Gt = 0x10,
+    Eq = 0x11,
+    And = 0x12,
+    Or = 0x13,
+    Xor = 0x14,
+    Not = 0x15,
+    Shl = 0x16,
+    Shr = 0x17,
+    JmpEq = 0x18,
+    JmpNeq = 0x19,
+    Jmp = 0x1a,
+    JmpOverflow = 0x1b,
+    Call = 0x1c,
+    Ret = 0x1d,
+    StdCall = 0x1e,
+    Interupt = 0x1f,
+    Halt = 0x20,
+    Store = 0x21,
+    Load = 0x22,
+    PushI16 = 0x23,
+    AddI16 = 0x24,
+    SubI16 = 0x25,
+    Neg = 0x26,
+    Min = 0x27,
+    Max = 0x28,
+    Depth = 0x29,
+    ClearStack = 0x2a,
+    PushAll = 0x2b,
+    Rot = 0x2c,
+    JmpLt = 0x2d,
+    JmpGt = 0x2e,
+    JmpLe = 0x2f,
+    JmpGe = 0x30,
+    ToBool = 0x31,
+    JmpIf = 0x32,
+    JmpIfNot = 0x33,
+    MemStore = 0x34,
+    MemLoad = 0x35,
+    Pick = 0x36,
+    DropN = 0x37,
+    Inc = 0x38,
+    Dec = 0x39,
+    PushChar = 0x3a,
+    Nop = 0x3b,
+    StoreArg = 0x3c,
+    LoadArg = 0x3d,
+    AddCarry = 0x3e,
+    SubBorrow = 0x3f,
+    Cmp = 0x40,
+    Assert = 0x41,
+    ModI = 0x42,
+    PushCountedStr = 0x43,
+    DupN = 0x44,
+    ToR = 0x45,
+    FromR = 0x46,
+    JmpRel = 0x47,
+    JmpEqRel = 0x48,
+    JmpNeqRel = 0x49,
+    JmpIfRel = 0x4a,
+    JmpIfNotRel = 0x4b,
+    SetLocal = 0x4c,
+    ToUpper = 0x4d,
+    ToLower = 0x4e,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Opcode::Push),
+            0x01 => Ok(Opcode::PushStr),
+            0x02 => Ok(Opcode::Pop),
+            0x03 => Ok(Opcode::Dup),
+            0x04 => Ok(Opcode::Swap),
+            0x05 => Ok(Opcode::Over),
+            0x06 => Ok(Opcode::Add),
+            0x07 => Ok(Opcode::Sub),
+            0x08 => Ok(Opcode::Mul),
+            0x09 => Ok(Opcode::Div),
+            0x0a => Ok(Opcode::Mod),
+            0x0b => Ok(Opcode::AddI),
+            0x0c => Ok(Opcode::SubI),
+            0x0d => Ok(Opcode::MulI),
+            0x0e => Ok(Opcode::DivI),
+            0x0f => Ok(Opcode::Lt),
+            0x10 => Ok(Opcode::Gt),
+            0x11 => Ok(Opcode::Eq),
+            0x12 => Ok(Opcode::And),
+            0x13 => Ok(Opcode::Or),
+            0x14 => Ok(Opcode::Xor),
+            0x15 => Ok(Opcode::Not),
+            0x16 => Ok(Opcode::Shl),
+            0x17 => Ok(Opcode::Shr),
+            0x18 => Ok(Opcode::JmpEq),
+            0x19 => Ok(Opcode::JmpNeq),
+            0x1a => Ok(Opcode::Jmp),
+            0x1b => Ok(Opcode::JmpOverflow),
+            0x1c => Ok(Opcode::Call),
+            0x1d => Ok(Opcode::Ret),
+            0x1e => Ok(Opcode::StdCall),
+            0x1f => Ok(Opcode::Interupt),
+            0x20 => Ok(Opcode::Halt),
+            0x21 => Ok(Opcode::Store),
+            0x22 => Ok(Opcode::Load),
+            0x23 => Ok(Opcode::PushI16),
+            0x24 => Ok(Opcode::AddI16),
+            0x25 => Ok(Opcode::SubI16),
+            0x26 => Ok(Opcode::Neg),
+            0x27 => Ok(Opcode::Min),
+            0x28 => Ok(Opcode::Max),
+            0x29 => Ok(Opcode::Depth),
+            0x2a => Ok(Opcode::ClearStack),
+            0x2b => Ok(Opcode::PushAll),
+            0x2c => Ok(Opcode::Rot),
+            0x2d => Ok(Opcode::JmpLt),
+            0x2e => Ok(Opcode::JmpGt),
+            0x2f => Ok(Opcode::JmpLe),
+            0x30 => Ok(Opcode::JmpGe),
+            0x31 => Ok(Opcode::ToBool),
+            0x32 => Ok(Opcode::JmpIf),
+            0x33 => Ok(Opcode::JmpIfNot),
+            0x34 => Ok(Opcode::MemStore),
+            0x35 => Ok(Opcode::MemLoad),
+            0x36 => Ok(Opcode::Pick),
+            0x37 => Ok(Opcode::DropN),
+            0x38 => Ok(Opcode::Inc),
+            0x39 => Ok(Opcode::Dec),
+            0x3a => Ok(Opcode::PushChar),
+            0x3b => Ok(Opcode::Nop),
+            0x3c => Ok(Opcode::StoreArg),
+            0x3d => Ok(Opcode::LoadArg),
+            0x3e => Ok(Opcode::AddCarry),
+            0x3f => Ok(Opcode::SubBorrow),
+            0x40 => Ok(Opcode::Cmp),
+            0x41 => Ok(Opcode::Assert),
+            0x42 => Ok(Opcode::ModI),
+            0x43 => Ok(Opcode::PushCountedStr),
+            0x44 => Ok(Opcode::DupN),
+            0x45 => Ok(Opcode::ToR),
+            0x46 => Ok(Opcode::FromR),
+            0x47 => Ok(Opcode::JmpRel),
+            0x48 => Ok(Opcode::JmpEqRel),
+            0x49 => Ok(Opcode::JmpNeqRel),
+            0x4a => Ok(Opcode::JmpIfRel),
+            0x4b => Ok(Opcode::JmpIfNotRel),
+            0x4c => Ok(Opcode::SetLocal),
+            0x4d => Ok(Opcode::ToUpper),
+            0x4e => Ok(Opcode::ToLower),
+            _ => Err(value),
+        }
+    }
+}
+
+impl<T> Instruction<T> {
+
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Instruction::Push(_) => Opcode::Push,
+            Instruction::PushStr(_) => Opcode::PushStr,
+            Instruction::PushCountedStr(_) => Opcode::PushCountedStr,
+            Instruction::PushChar(_) => Opcode::PushChar,
+            Instruction::PushAll(_) => Opcode::PushAll,
+            Instruction::Pop => Opcode::Pop,
+            Instruction::DropN(_) => Opcode::DropN,
+            Instruction::Dup => Opcode::Dup,
+            Instruction::DupN(_) => Opcode::DupN,
+            Instruction::Swap => Opcode::Swap,
+            Instruction::Over => Opcode::Over,
+            Instruction::Pick(_) => Opcode::Pick,
+            Instruction::Rot(_) => Opcode::Rot,
+            Instruction::Depth => Opcode::Depth,
+            Instruction::ClearStack => Opcode::ClearStack,
+            Instruction::Nop => Opcode::Nop,
+            Instruction::Store(_) => Opcode::Store,
+            Instruction::Load(_) => Opcode::Load,
+            Instruction::SetLocal(_, _) => Opcode::SetLocal,
+            Instruction::StoreArg(_) => Opcode::StoreArg,
+            Instruction::LoadArg(_) => Opcode::LoadArg,
+            Instruction::MemStore => Opcode::MemStore,
+            Instruction::MemLoad => Opcode::MemLoad,
+            Instruction::Add => Opcode::Add,
+            Instruction::Sub => Opcode::Sub,
+            Instruction::Mul => Opcode::Mul,
+            Instruction::Div => Opcode::Div,
+            Instruction::Mod => Opcode::Mod,
+            Instruction::Neg => Opcode::Neg,
+            Instruction::Inc => Opcode::Inc,
+            Instruction::Dec => Opcode::Dec,
+            Instruction::AddCarry => Opcode::AddCarry,
+            Instruction::SubBorrow => Opcode::SubBorrow,
+            Instruction::AddI => Opcode::AddI,
+            Instruction::SubI => Opcode::SubI,
+            Instruction::MulI => Opcode::MulI,
+            Instruction::DivI => Opcode::DivI,
+            Instruction::ModI => Opcode::ModI,
+            Instruction::PushI16(_) => Opcode::PushI16,
+            Instruction::AddI16 => Opcode::AddI16,
+            Instruction::SubI16 => Opcode::SubI16,
+            Instruction::Lt => Opcode::Lt,
+            Instruction::Gt => Opcode::Gt,
+            Instruction::Eq => Opcode::Eq,
+            Instruction::Cmp => Opcode::Cmp,
+            Instruction::Min => Opcode::Min,
+            Instruction::Max => Opcode::Max,
+            Instruction::ToBool => Opcode::ToBool,
+            Instruction::ToUpper => Opcode::ToUpper,
+            Instruction::ToLower => Opcode::ToLower,
+            Instruction::And => Opcode::And,
+            Instruction::Or => Opcode::Or,
+            Instruction::Xor => Opcode::Xor,
+            Instruction::Not => Opcode::Not,
+            Instruction::Shl => Opcode::Shl,
+            Instruction::Shr => Opcode::Shr,
+            Instruction::JmpEq(_) => Opcode::JmpEq,
+            Instruction::JmpNeq(_) => Opcode::JmpNeq,
+            Instruction::JmpLt(_) => Opcode::JmpLt,
+            Instruction::JmpGt(_) => Opcode::JmpGt,
+            Instruction::JmpLe(_) => Opcode::JmpLe,
+            Instruction::JmpGe(_) => Opcode::JmpGe,
+            Instruction::Jmp(_) => Opcode::Jmp,
+            Instruction::JmpOverflow(_) => Opcode::JmpOverflow,
+            Instruction::JmpIf(_) => Opcode::JmpIf,
+            Instruction::JmpIfNot(_) => Opcode::JmpIfNot,
+            Instruction::JmpRel(_) => Opcode::JmpRel,
+            Instruction::JmpEqRel(_) => Opcode::JmpEqRel,
+            Instruction::JmpNeqRel(_) => Opcode::JmpNeqRel,
+            Instruction::JmpIfRel(_) => Opcode::JmpIfRel,
+            Instruction::JmpIfNotRel(_) => Opcode::JmpIfNotRel,
+            Instruction::Call(_) => Opcode::Call,
+            Instruction::Ret => Opcode::Ret,
+            Instruction::ToR => Opcode::ToR,
+            Instruction::FromR => Opcode::FromR,
+            Instruction::StdCall(_) => Opcode::StdCall,
+            Instruction::Assert(_) => Opcode::Assert,
+            Instruction::Halt(_) => Opcode::Halt,
+            Instruction::Interupt => Opcode::Interupt,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // One of each variant, in declaration order
+    fn every_instruction() -> Vec<Instruction> {
+        vec![
+            Instruction::Push(1), Instruction::PushStr("a".to_string()), Instruction::PushCountedStr("a".to_string()),
+            Instruction::PushChar('a'), Instruction::PushAll(vec![1]), Instruction::Pop, Instruction::DropN(1),
+            Instruction::Dup, Instruction::DupN(1), Instruction::Swap, Instruction::Over, Instruction::Pick(1),
+            Instruction::Rot(1), Instruction::Depth, Instruction::ClearStack, Instruction::Nop, Instruction::Store(1),
+            Instruction::Load(1), Instruction::SetLocal(0, 1), Instruction::StoreArg(1), Instruction::LoadArg(1),
+            Instruction::MemStore, Instruction::MemLoad, Instruction::Add, Instruction::Sub, Instruction::Mul,
+            Instruction::Div, Instruction::Mod, Instruction::Neg, Instruction::Inc, Instruction::Dec,
+            Instruction::AddCarry, Instruction::SubBorrow, Instruction::AddI, Instruction::SubI, Instruction::MulI,
+            Instruction::DivI, Instruction::ModI, Instruction::PushI16(1), Instruction::AddI16, Instruction::SubI16,
+            Instruction::Lt, Instruction::Gt, Instruction::Eq, Instruction::Cmp, Instruction::Min, Instruction::Max,
+            Instruction::ToBool, Instruction::ToUpper, Instruction::ToLower, Instruction::And, Instruction::Or,
+            Instruction::Xor, Instruction::Not, Instruction::Shl, Instruction::Shr, Instruction::JmpEq(0),
+            Instruction::JmpNeq(0), Instruction::JmpLt(0), Instruction::JmpGt(0), Instruction::JmpLe(0),
+            Instruction::JmpGe(0), Instruction::Jmp(0), Instruction::JmpOverflow(0), Instruction::JmpIf(0),
+            Instruction::JmpIfNot(0), Instruction::JmpRel(-1), Instruction::JmpEqRel(-1), Instruction::JmpNeqRel(-1),
+            Instruction::JmpIfRel(-1), Instruction::JmpIfNotRel(-1), Instruction::Call(0), Instruction::Ret,
+            Instruction::ToR, Instruction::FromR, Instruction::StdCall(0), Instruction::Assert(1), Instruction::Halt(1),
+            Instruction::Interupt,
+        ]
+    }
+
+    #[test]
+    fn opcodes_are_unique_bytes() {
+        let instructions = every_instruction();
+        assert_eq!(instructions.len(), 79);
+        let bytes: std::collections::HashSet<u8> = instructions.iter().map(|instruction| instruction.opcode() as u8).collect();
+        assert_eq!(bytes.len(), instructions.len());
+        for instruction in &instructions {
+            assert_eq!(Opcode::try_from(instruction.opcode() as u8), Ok(instruction.opcode()));
+        }
+        assert_eq!((0..=255u8).filter(|byte| Opcode::try_from(*byte).is_ok()).count(), bytes.len());
+        assert_eq!(Opcode::try_from(0xff), Err(0xff));
+    }
+
+    #[test]
+    fn instructions_map_to_their_opcode() {
+        assert_eq!(Instruction::<u8>::Pick(2).opcode(), Opcode::Pick);
+        assert_eq!(Instruction::<u8>::Jmp(5).opcode() as u8, 0x1a);
+        assert_eq!(Instruction::<u8>::Halt(3).opcode() as u8, 0x20);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use crate::{Cell, Program, VmError, VM};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileReport {
+    // Instructions executed per call stack, each subroutine is identified by its entry point
+    samples: HashMap<Vec<usize>, u64>,
+}
+
+impl ProfileReport {
+
+    // Instructions executed directly inside the subroutine starting at `entry`, callees excluded
+    pub fn self_count(&self, entry: usize) -> u64 {
+        self.samples.iter()
+            .filter(|(stack, _)| stack.last() == Some(&entry))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    // Instructions executed outside of any subroutine
+    pub fn top_level_count(&self) -> u64 {
+        self.samples.get(&Vec::new()).copied().unwrap_or(0)
+    }
+
+    // One `main;entry;entry count` line per call stack, the folded format read by inferno and flamegraph.pl
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.samples.iter()
+            .map(|(stack, count)| {
+                let mut frames = vec!["main".to_string()];
+                frames.extend(stack.iter().map(|entry| entry.to_string()));
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+}
+
+impl<T: Cell> VM<T> {
+
+    pub fn execute_with_profile(&mut self, program: &Program<T>) -> Result<ProfileReport, VmError> {
+        let mut report = ProfileReport::default();
+        let mut stack = Vec::new();
+        loop {
+            *report.samples.entry(stack.clone()).or_insert(0) += 1;
+            let depth = self.call_stack.len();
+            if !self.execute_one(program)? {
+                return Ok(report);
+            }
+            // A call lands on the subroutine entry, a return leaves the innermost one
+            if self.call_stack.len() > depth {
+                stack.push(self.program_pointer);
+            } else if self.call_stack.len() < depth {
+                stack.pop();
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn instructions_are_counted_per_subroutine() {
+        let program = Program::parse("
+            call a
+            call b
+            halt 0
+        a:
+            nop
+            call b
+            ret
+        b:
+            nop
+            nop
+            ret
+        ").unwrap();
+        let report = VM::new().execute_with_profile(&program).unwrap();
+        assert_eq!(report.top_level_count(), 3);
+        assert_eq!(report.self_count(3), 3);
+        assert_eq!(report.self_count(6), 6);
+        assert_eq!(report.folded(), "main 3\nmain;3 3\nmain;3;6 3\nmain;6 3\n");
+    }
+}